@@ -0,0 +1,614 @@
+use array2d::Array2D;
+use itertools::{iproduct, Itertools};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// The cells a rule looks at, as offsets from the cell being updated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighborhood {
+    pub offsets: Vec<[i64; 2]>,
+}
+
+impl Neighborhood {
+    /// Every cell within `radius` rows and columns, including the center,
+    /// in row-major order.
+    pub fn square(radius: i64) -> Neighborhood {
+        Neighborhood {
+            offsets: iproduct!(-radius..=radius, -radius..=radius)
+                .map(|(dr, dc)| [dr, dc])
+                .collect(),
+        }
+    }
+
+    /// Like `square`, without the center.
+    pub fn moore(radius: i64) -> Neighborhood {
+        let mut neighborhood = Neighborhood::square(radius);
+        neighborhood.offsets.retain(|o| *o != [0, 0]);
+        neighborhood
+    }
+
+    /// Every cell within manhattan distance `radius`, without the center.
+    pub fn von_neumann(radius: i64) -> Neighborhood {
+        let mut neighborhood = Neighborhood::moore(radius);
+        neighborhood
+            .offsets
+            .retain(|o| o[0].abs() + o[1].abs() <= radius);
+        neighborhood
+    }
+
+    /// How far the neighborhood reaches along either axis.
+    pub fn radius(&self) -> usize {
+        self.offsets
+            .iter()
+            .map(|o| std::cmp::max(o[0].abs(), o[1].abs()) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// What lies beyond the edges of the grid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Boundary<S> {
+    /// Nothing: cells outside the grid do not exist.
+    Finite,
+    /// Infinitely many cells in one state. The grid grows to hold every cell
+    /// that could differ from it, so states are compared by the cells that
+    /// do differ from it and where they are.
+    Background(S),
+    /// The grid wraps around at the edges.
+    Toroidal,
+}
+
+/// A cascading update: every cell is charged, then each cell that fires
+/// passes a charge to its neighbors, at most once per step, and finally
+/// every cell settles.
+#[derive(Clone)]
+pub struct Cascade<S> {
+    pub neighborhood: Neighborhood,
+    pub charge: Rc<dyn Fn(&S) -> S>,
+    pub fires: Rc<dyn Fn(&S) -> bool>,
+    pub receive: Rc<dyn Fn(&S) -> S>,
+    pub settle: Rc<dyn Fn(&S) -> S>,
+}
+
+/// One phase of a phased update: every `mover` whose cell at `offset` is
+/// `empty` moves there, all at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase<S> {
+    pub mover: S,
+    pub offset: [i64; 2],
+    pub empty: S,
+}
+
+#[derive(Clone)]
+pub enum Update<S> {
+    /// Every cell is replaced by `rule(cell, neighbors)` at once, with the
+    /// neighbors in the order of `neighborhood`. Neighbors outside a finite
+    /// grid are `None`.
+    Synchronous {
+        neighborhood: Neighborhood,
+        rule: Rc<dyn Fn(&S, &[Option<S>]) -> S>,
+    },
+    Cascading(Cascade<S>),
+    /// The phases run one after another within each step. Each mover only
+    /// looks at the cell it would move into.
+    Phased(Vec<Phase<S>>),
+}
+
+/// A state that repeats: the state after `start` steps is the same as after
+/// `start + period` steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    pub start: usize,
+    pub period: usize,
+}
+
+/// A hash of a state, for `find_repeat`.
+pub fn hash_state<T: Hash + ?Sized>(state: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Steps `state` until it repeats, or `None` if it does not within
+/// `max_steps` steps, leaving it at the repeat.
+///
+/// Only hashes of the states are kept. When a hash comes up again, the
+/// earlier state is rebuilt by replaying `step` on a copy of the first one
+/// and compared with `same`, so a hash collision is never reported as a
+/// cycle.
+pub fn find_repeat<T: Clone>(
+    state: &mut T,
+    max_steps: usize,
+    mut step: impl FnMut(&mut T),
+    hash: impl Fn(&T) -> u64,
+    same: impl Fn(&T, &T) -> bool,
+) -> Option<Cycle> {
+    let initial = state.clone();
+    let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+    for index in 0..=max_steps {
+        if index > 0 {
+            step(state);
+        }
+        let earlier = seen.entry(hash(state)).or_default();
+        for &start in earlier.iter() {
+            let mut past = initial.clone();
+            for _ in 0..start {
+                step(&mut past);
+            }
+            if same(&past, state) {
+                return Some(Cycle {
+                    start,
+                    period: index - start,
+                });
+            }
+        }
+        earlier.push(index);
+    }
+    None
+}
+
+#[derive(Clone)]
+pub struct Automaton<S: Clone> {
+    pub grid: Array2D<S>,
+    pub boundary: Boundary<S>,
+    update: Update<S>,
+    /// Where the top left cell of the grid started out, which moves as a
+    /// background grid grows.
+    origin: [i64; 2],
+    /// Steps taken so far.
+    steps: usize,
+    /// The cells that fired in the last cascading step, in order.
+    fired: Vec<(usize, usize)>,
+}
+
+impl<S: Clone + Eq + Hash> Automaton<S> {
+    pub fn new(grid: Array2D<S>, boundary: Boundary<S>, update: Update<S>) -> Automaton<S> {
+        if let Update::Phased(_) = update {
+            assert!(
+                !matches!(boundary, Boundary::Background(_)),
+                "phased updates need a bounded grid"
+            );
+        }
+        Automaton {
+            grid,
+            boundary,
+            update,
+            origin: [0, 0],
+            steps: 0,
+            fired: vec![],
+        }
+    }
+
+    /// The grid position of (`row`, `col`) after wrapping, if it is on the
+    /// grid.
+    fn position(&self, row: i64, col: i64) -> Option<(usize, usize)> {
+        let (rows, columns) = (self.grid.num_rows() as i64, self.grid.num_columns() as i64);
+        let (row, col) = match self.boundary {
+            Boundary::Toroidal => (row.rem_euclid(rows), col.rem_euclid(columns)),
+            _ => (row, col),
+        };
+        if row < 0 || col < 0 || row >= rows || col >= columns {
+            None
+        } else {
+            Some((row as usize, col as usize))
+        }
+    }
+
+    /// The cell at (`row`, `col`), which may lie off the grid.
+    fn get(&self, row: i64, col: i64) -> Option<S> {
+        match (self.position(row, col), &self.boundary) {
+            (Some(position), _) => Some(self.grid[position].clone()),
+            (None, Boundary::Background(background)) => Some(background.clone()),
+            (None, _) => None,
+        }
+    }
+
+    fn neighbors(&self, neighborhood: &Neighborhood, row: i64, col: i64) -> Vec<Option<S>> {
+        neighborhood
+            .offsets
+            .iter()
+            .map(|o| self.get(row + o[0], col + o[1]))
+            .collect()
+    }
+
+    /// How many cells are in `state`, or `None` if infinitely many are.
+    pub fn count(&self, state: &S) -> Option<usize> {
+        if self.boundary == Boundary::Background(state.clone()) {
+            return None;
+        }
+        Some(
+            self.grid
+                .elements_row_major_iter()
+                .filter(|s| *s == state)
+                .count(),
+        )
+    }
+
+    /// The cells that fired in the last step, in the order they fired, if
+    /// it was a cascade.
+    pub fn fired(&self) -> &[(usize, usize)] {
+        &self.fired
+    }
+
+    /// Runs one step, returning how many cells changed, or fired for
+    /// cascades.
+    pub fn step(&mut self) -> usize {
+        self.fired.clear();
+        let activity = match &self.update {
+            Update::Synchronous { neighborhood, rule } => {
+                // Grow a background grid by the neighborhood's reach.
+                let margin = match self.boundary {
+                    Boundary::Background(_) => neighborhood.radius() as i64,
+                    _ => 0,
+                };
+                let rows = self.grid.num_rows() + 2 * margin as usize;
+                let columns = self.grid.num_columns() + 2 * margin as usize;
+                let mut changed = 0;
+                let cells = iproduct!(0..rows as i64, 0..columns as i64)
+                    .map(|(r, c)| {
+                        let (r, c) = (r - margin, c - margin);
+                        let cell = self.get(r, c).unwrap();
+                        let next = rule(&cell, &self.neighbors(neighborhood, r, c));
+                        changed += (next != cell) as usize;
+                        next
+                    })
+                    .collect_vec();
+                if let Boundary::Background(background) = &self.boundary {
+                    let next = rule(
+                        background,
+                        &vec![Some(background.clone()); neighborhood.offsets.len()],
+                    );
+                    self.boundary = Boundary::Background(next);
+                }
+                self.grid = Array2D::from_row_major(&cells, rows, columns);
+                self.origin = self.origin.map(|x| x - margin);
+                changed
+            }
+            Update::Cascading(cascade) => {
+                let (rows, columns) = (self.grid.num_rows(), self.grid.num_columns());
+                let mut grid = self.grid.clone();
+                let mut fired = Array2D::filled_with(false, rows, columns);
+                let mut stack = vec![];
+                for (r, c) in iproduct!(0..rows, 0..columns) {
+                    grid[(r, c)] = (cascade.charge)(&grid[(r, c)]);
+                    if (cascade.fires)(&grid[(r, c)]) {
+                        fired[(r, c)] = true;
+                        stack.push((r, c));
+                    }
+                }
+                while let Some((r, c)) = stack.pop() {
+                    self.fired.push((r, c));
+                    for o in &cascade.neighborhood.offsets {
+                        if let Some(p) = self.position(r as i64 + o[0], c as i64 + o[1]) {
+                            grid[p] = (cascade.receive)(&grid[p]);
+                            if (cascade.fires)(&grid[p]) && !fired[p] {
+                                fired[p] = true;
+                                stack.push(p);
+                            }
+                        }
+                    }
+                }
+                for (r, c) in iproduct!(0..rows, 0..columns) {
+                    grid[(r, c)] = (cascade.settle)(&grid[(r, c)]);
+                }
+                self.grid = grid;
+                self.fired.len()
+            }
+            Update::Phased(phases) => {
+                let mut moved = 0;
+                for phase in phases {
+                    let moves = iproduct!(0..self.grid.num_rows(), 0..self.grid.num_columns())
+                        .filter(|p| self.grid[*p] == phase.mover)
+                        .filter_map(|(r, c)| {
+                            let target = self
+                                .position(r as i64 + phase.offset[0], c as i64 + phase.offset[1])?;
+                            (self.grid[target] == phase.empty).then(|| ((r, c), target))
+                        })
+                        .collect_vec();
+                    for (start, end) in &moves {
+                        self.grid[*start] = phase.empty.clone();
+                        self.grid[*end] = phase.mover.clone();
+                    }
+                    moved += moves.len();
+                }
+                moved
+            }
+        };
+        self.steps += 1;
+        activity
+    }
+
+    /// Steps until a step changes nothing, returning the number of that
+    /// step, or `None` if that takes more than `max_steps` steps.
+    pub fn run_until_stable(&mut self, max_steps: usize) -> Option<usize> {
+        for _ in 0..max_steps {
+            if self.step() == 0 {
+                return Some(self.steps);
+            }
+        }
+        None
+    }
+
+    /// The part of the grid that makes up the state, and where its top
+    /// left cell is if that matters. On a background only the smallest box
+    /// holding every cell that differs from it counts, along with where the
+    /// box is, since the grid grows even when nothing else changes.
+    fn region(&self) -> (Option<[i64; 2]>, Range<usize>, Range<usize>) {
+        let (rows, columns) = (self.grid.num_rows(), self.grid.num_columns());
+        match &self.boundary {
+            Boundary::Background(background) => {
+                let differing = iproduct!(0..rows, 0..columns)
+                    .filter(|p| self.grid[*p] != *background)
+                    .collect_vec();
+                let row_range = differing.iter().map(|p| p.0).minmax().into_option();
+                let column_range = differing.iter().map(|p| p.1).minmax().into_option();
+                match (row_range, column_range) {
+                    (Some((top, bottom)), Some((left, right))) => (
+                        Some([self.origin[0] + top as i64, self.origin[1] + left as i64]),
+                        top..bottom + 1,
+                        left..right + 1,
+                    ),
+                    _ => (None, 0..0, 0..0),
+                }
+            }
+            _ => (None, 0..rows, 0..columns),
+        }
+    }
+
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.boundary.hash(&mut hasher);
+        let (corner, rows, columns) = self.region();
+        corner.hash(&mut hasher);
+        [rows.len(), columns.len()].hash(&mut hasher);
+        for p in iproduct!(rows, columns) {
+            self.grid[p].hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn same_state(&self, other: &Automaton<S>) -> bool {
+        let (corner, rows, columns) = self.region();
+        let (other_corner, other_rows, other_columns) = other.region();
+        self.boundary == other.boundary
+            && corner == other_corner
+            && (rows.len(), columns.len()) == (other_rows.len(), other_columns.len())
+            && iproduct!(rows, columns)
+                .zip(iproduct!(other_rows, other_columns))
+                .all(|(p, q)| self.grid[p] == other.grid[q])
+    }
+
+    /// Steps until the whole state repeats, or `None` if it does not
+    /// within `max_steps` steps. See `find_repeat`.
+    pub fn find_cycle(&mut self, max_steps: usize) -> Option<Cycle> {
+        let start = self.steps;
+        find_repeat(
+            self,
+            max_steps,
+            |automaton| {
+                automaton.step();
+            },
+            Automaton::state_hash,
+            Automaton::same_state,
+        )
+        .map(|cycle| Cycle {
+            start: start + cycle.start,
+            ..cycle
+        })
+    }
+}
+
+#[cfg(test)]
+fn life(cell: &bool, neighbors: &[Option<bool>]) -> bool {
+    let alive = neighbors.iter().filter(|n| **n == Some(true)).count();
+    alive == 3 || (*cell && alive == 2)
+}
+
+#[cfg(test)]
+fn parse_cells(rows: &[&str]) -> Array2D<bool> {
+    Array2D::from_rows(
+        &rows
+            .iter()
+            .map(|r| r.chars().map(|c| c == '#').collect_vec())
+            .collect_vec(),
+    )
+}
+
+#[test]
+fn test_life_cycles() {
+    let blinker = parse_cells(&[".....", "..#..", "..#..", "..#..", "....."]);
+    let mut automaton = Automaton::new(
+        blinker.clone(),
+        Boundary::Finite,
+        Update::Synchronous {
+            neighborhood: Neighborhood::moore(1),
+            rule: Rc::new(life),
+        },
+    );
+    assert_eq!(
+        automaton.find_cycle(10),
+        Some(Cycle {
+            start: 0,
+            period: 2
+        })
+    );
+    assert_eq!(automaton.steps, 2);
+    assert_eq!(automaton.count(&true), Some(3));
+    assert_eq!(automaton.step(), 4);
+
+    // A glider on a torus comes back where it started after moving a
+    // full lap diagonally.
+    let glider = parse_cells(&[".#...", "..#..", "###..", ".....", "....."]);
+    let mut automaton = Automaton::new(
+        glider,
+        Boundary::Toroidal,
+        Update::Synchronous {
+            neighborhood: Neighborhood::moore(1),
+            rule: Rc::new(life),
+        },
+    );
+    assert_eq!(
+        automaton.find_cycle(100),
+        Some(Cycle {
+            start: 0,
+            period: 20
+        })
+    );
+
+    // The same glider on a finite grid hits the corner and becomes a block.
+    let glider = parse_cells(&[".#...", "..#..", "###..", ".....", "....."]);
+    let mut automaton = Automaton::new(
+        glider,
+        Boundary::Finite,
+        Update::Synchronous {
+            neighborhood: Neighborhood::moore(1),
+            rule: Rc::new(life),
+        },
+    );
+    assert_eq!(automaton.run_until_stable(100), Some(12));
+    assert_eq!(automaton.count(&true), Some(4));
+}
+
+#[test]
+fn test_background() {
+    // Every cell inverts, including the infinite background, and the grid
+    // grows by the neighborhood's reach each step.
+    let mut automaton = Automaton::new(
+        parse_cells(&["#."]),
+        Boundary::Background(false),
+        Update::Synchronous {
+            neighborhood: Neighborhood::von_neumann(1),
+            rule: Rc::new(|cell: &bool, _: &[Option<bool>]| !cell),
+        },
+    );
+    assert_eq!(automaton.count(&true), Some(1));
+    assert_eq!(automaton.step(), 12);
+    assert_eq!(automaton.boundary, Boundary::Background(true));
+    assert_eq!(
+        (automaton.grid.num_rows(), automaton.grid.num_columns()),
+        (3, 4)
+    );
+    assert_eq!(automaton.count(&true), None);
+    assert_eq!(automaton.count(&false), Some(1));
+    // The grid keeps growing, but only the cells that differ from the
+    // background count towards the state.
+    assert_eq!(
+        automaton.find_cycle(10),
+        Some(Cycle {
+            start: 1,
+            period: 2
+        })
+    );
+
+    // A blinker in an infinite plane, and a glider that never comes back.
+    let blinker = parse_cells(&["###"]);
+    let mut automaton = Automaton::new(
+        blinker,
+        Boundary::Background(false),
+        Update::Synchronous {
+            neighborhood: Neighborhood::moore(1),
+            rule: Rc::new(life),
+        },
+    );
+    assert_eq!(
+        automaton.find_cycle(10),
+        Some(Cycle {
+            start: 0,
+            period: 2
+        })
+    );
+    let glider = parse_cells(&[".#.", "..#", "###"]);
+    let mut automaton = Automaton::new(
+        glider,
+        Boundary::Background(false),
+        Update::Synchronous {
+            neighborhood: Neighborhood::moore(1),
+            rule: Rc::new(life),
+        },
+    );
+    assert_eq!(automaton.find_cycle(40), None);
+    assert_eq!(automaton.steps, 40);
+    assert_eq!(automaton.count(&true), Some(5));
+}
+
+#[test]
+fn test_find_repeat() {
+    // A hash that always collides still only finds the real cycle.
+    let mut count = 3;
+    let cycle = find_repeat(&mut count, 20, |n| *n = (*n + 1) % 5, |_| 0, |a, b| a == b);
+    assert_eq!(
+        cycle,
+        Some(Cycle {
+            start: 0,
+            period: 5
+        })
+    );
+    assert_eq!(count, 3);
+    let mut count = 0u64;
+    assert_eq!(
+        find_repeat(&mut count, 20, |n| *n += 1, |_| 0, |a, b| a == b),
+        None
+    );
+    assert_eq!(count, 20);
+}
+
+#[test]
+fn test_cascade_and_phases() {
+    // Each cell fires once it reaches 3, passing 1 to its four neighbors.
+    let mut automaton = Automaton::new(
+        Array2D::from_rows(&[vec![2, 0, 0], vec![0, 0, 0], vec![0, 0, 2]]),
+        Boundary::Finite,
+        Update::Cascading(Cascade {
+            neighborhood: Neighborhood::von_neumann(1),
+            charge: Rc::new(|e| e + 1),
+            fires: Rc::new(|e| *e >= 3),
+            receive: Rc::new(|e| e + 1),
+            settle: Rc::new(|e| if *e >= 3 { 0 } else { *e }),
+        }),
+    );
+    assert_eq!(automaton.step(), 2);
+    assert_eq!(
+        automaton.grid,
+        Array2D::from_rows(&[vec![0, 2, 1], vec![2, 1, 2], vec![1, 2, 0]])
+    );
+    // Now a single charge sets off the whole grid.
+    assert_eq!(automaton.step(), 9);
+    assert_eq!(automaton.count(&0), Some(9));
+
+    // Movers blocked by the edge of a finite grid stay put; a torus lets
+    // them wrap around.
+    let phases = || {
+        Update::Phased(vec![
+            Phase {
+                mover: '>',
+                offset: [0, 1],
+                empty: '.',
+            },
+            Phase {
+                mover: 'v',
+                offset: [1, 0],
+                empty: '.',
+            },
+        ])
+    };
+    let grid = Array2D::from_rows(&[vec!['.', '>'], vec!['v', '.']]);
+    let mut automaton = Automaton::new(grid.clone(), Boundary::Finite, phases());
+    assert_eq!(automaton.run_until_stable(10), Some(1));
+    let mut automaton = Automaton::new(grid, Boundary::Toroidal, phases());
+    // The down mover is blocked by the right mover that arrived earlier in
+    // the same step.
+    assert_eq!(automaton.step(), 1);
+    assert_eq!(
+        automaton.grid,
+        Array2D::from_rows(&[vec!['>', '.'], vec!['v', '.']])
+    );
+    assert_eq!(automaton.step(), 2);
+    assert_eq!(
+        automaton.grid,
+        Array2D::from_rows(&[vec!['v', '>'], vec!['.', '.']])
+    );
+}
@@ -1,38 +1,136 @@
 use itertools::Itertools;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, BufRead};
+use std::iter::Sum;
+use std::ops::Add;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Node {
     Pair(Box<Node>, Box<Node>),
     Literal(i64),
 }
 
+/// The rules used to reduce and weigh snailfish numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rules {
+    /// Pairs nested inside this many pairs explode.
+    explode_depth: usize,
+    /// Regular numbers at least this large split. Must be at least 2, or
+    /// reduction never terminates.
+    split_threshold: i64,
+    /// Weights of the left and right element of a pair in its magnitude.
+    magnitude_weights: (i64, i64),
+}
+
+const PUZZLE_RULES: Rules = Rules {
+    explode_depth: 4,
+    split_threshold: 10,
+    magnitude_weights: (3, 2),
+};
+
+impl Default for Rules {
+    fn default() -> Rules {
+        PUZZLE_RULES
+    }
+}
+
+struct Explosion {
+    /// The values of the pair that exploded.
+    pair: (i64, i64),
+    /// The values still to be added to the nearest regular number on each side.
+    carry: (Option<i64>, Option<i64>),
+}
+
+/// A single action taken while reducing a snailfish number. `position` is the
+/// index among the regular numbers, from the left, of the exploded pair's
+/// left value or of the split number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ReductionAction {
+    Explode {
+        position: usize,
+        left: i64,
+        right: i64,
+    },
+    Split {
+        position: usize,
+        value: i64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ReductionStep {
+    action: ReductionAction,
+    /// The number after the action, in canonical form.
+    expression: String,
+}
+
+impl fmt::Display for ReductionStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            ReductionAction::Explode { .. } => write!(f, "after explode:  {}", self.expression),
+            ReductionAction::Split { .. } => write!(f, "after split:    {}", self.expression),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ParseError {
+    UnexpectedEnd,
+    UnexpectedChar {
+        position: usize,
+        found: char,
+    },
+    TrailingInput {
+        position: usize,
+    },
+    /// A regular number too large to store.
+    NumberTooLarge {
+        position: usize,
+    },
+}
+
+fn expect(string: &[u8], position: usize, expected: u8) -> Result<(), ParseError> {
+    match string.get(position) {
+        Some(&c) if c == expected => Ok(()),
+        Some(&c) => Err(ParseError::UnexpectedChar {
+            position,
+            found: c as char,
+        }),
+        None => Err(ParseError::UnexpectedEnd),
+    }
+}
+
 impl Node {
     /// Returns the node, and the end index in the string.
-    fn parse(string: &[u8], start: usize) -> (Node, usize) {
-        if string[start] as char == '[' {
-            let (left, ind) = Node::parse(string, start + 1);
-            assert_eq!(string[ind] as char, ',');
-            let (right, last_ind) = Node::parse(string, ind + 1);
-            assert_eq!(string[last_ind] as char, ']');
-            return (Node::Pair(Box::from(left), Box::from(right)), last_ind + 1);
-        } else {
-            let end_ind = start
-                + string
-                    .iter()
-                    .skip(start)
-                    .position(|&c| c == b',' || c == b']')
-                    .unwrap_or(string.len());
-            return (
-                Node::Literal(
-                    std::str::from_utf8(&string[start..end_ind])
-                        .unwrap()
-                        .parse()
-                        .unwrap(),
-                ),
-                end_ind,
-            );
+    fn parse(string: &[u8], start: usize) -> Result<(Node, usize), ParseError> {
+        match string.get(start) {
+            Some(b'[') => {
+                let (left, ind) = Node::parse(string, start + 1)?;
+                expect(string, ind, b',')?;
+                let (right, last_ind) = Node::parse(string, ind + 1)?;
+                expect(string, last_ind, b']')?;
+                Ok((Node::Pair(Box::from(left), Box::from(right)), last_ind + 1))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let end_ind = start
+                    + string
+                        .iter()
+                        .skip(start)
+                        .take_while(|c| c.is_ascii_digit())
+                        .count();
+                let value = std::str::from_utf8(&string[start..end_ind])
+                    .unwrap()
+                    .parse()
+                    .map_err(|_| ParseError::NumberTooLarge { position: start })?;
+                Ok((Node::Literal(value), end_ind))
+            }
+            Some(&c) => Err(ParseError::UnexpectedChar {
+                position: start,
+                found: c as char,
+            }),
+            None => Err(ParseError::UnexpectedEnd),
         }
     }
     fn add_left(&mut self, val: i64) {
@@ -56,124 +154,734 @@ impl Node {
         }
     }
 
-    fn reduce_explode(&mut self, depth: usize) -> Option<(Option<i64>, Option<i64>)> {
+    /// Explodes the leftmost pair nested deeply enough. `position` counts
+    /// the regular numbers to the left, so on success it is the index of the
+    /// exploded pair's left value.
+    fn reduce_explode(
+        &mut self,
+        depth: usize,
+        position: &mut usize,
+        rules: &Rules,
+    ) -> Option<Explosion> {
         match self {
             Node::Pair(left, right) => {
-                if depth >= 4 {
+                if depth >= rules.explode_depth {
                     if let Node::Literal(left_val) = **left {
                         if let Node::Literal(right_val) = **right {
                             let (l, r) = (left_val, right_val);
                             *self = Node::Literal(0);
-                            return Some((Some(l), Some(r)));
+                            return Some(Explosion {
+                                pair: (l, r),
+                                carry: (Some(l), Some(r)),
+                            });
                         }
                     }
                 }
-                if let Some(left_explode) = left.reduce_explode(depth + 1) {
-                    if let Some(right_increment) = left_explode.1 {
+                if let Some(left_explode) = left.reduce_explode(depth + 1, position, rules) {
+                    if let Some(right_increment) = left_explode.carry.1 {
                         right.add_left(right_increment);
                     }
-                    return Some((left_explode.0, None));
+                    return Some(Explosion {
+                        carry: (left_explode.carry.0, None),
+                        ..left_explode
+                    });
                 }
-                if let Some(right_explode) = right.reduce_explode(depth + 1) {
-                    if let Some(left_increment) = right_explode.0 {
+                if let Some(right_explode) = right.reduce_explode(depth + 1, position, rules) {
+                    if let Some(left_increment) = right_explode.carry.0 {
                         left.add_right(left_increment);
                     }
-                    return Some((None, right_explode.1));
+                    return Some(Explosion {
+                        carry: (None, right_explode.carry.1),
+                        ..right_explode
+                    });
                 }
                 return None;
             }
             Node::Literal(_) => {
+                *position += 1;
                 return None;
             }
         }
     }
 
-    fn split_first(&mut self) -> bool {
+    /// Splits the leftmost regular number that is too large, returning its
+    /// value. `position` counts the regular numbers to the left.
+    fn split_first(&mut self, position: &mut usize, rules: &Rules) -> Option<i64> {
         match self {
             Node::Literal(val) => {
-                if *val >= 10 {
+                if *val >= rules.split_threshold {
+                    let value = *val;
                     *self = Node::Pair(
-                        Box::from(Node::Literal(*val / 2)),
-                        Box::from(Node::Literal(*val - *val / 2)),
+                        Box::from(Node::Literal(value / 2)),
+                        Box::from(Node::Literal(value - value / 2)),
                     );
-                    return true;
+                    return Some(value);
                 }
+                *position += 1;
             }
             Node::Pair(left, right) => {
-                if left.split_first() {
-                    return true;
-                } else if right.split_first() {
-                    return true;
+                if let Some(value) = left.split_first(position, rules) {
+                    return Some(value);
+                } else if let Some(value) = right.split_first(position, rules) {
+                    return Some(value);
                 }
             }
         }
-        return false;
+        return None;
     }
-    fn reduce(&mut self) {
-        loop {
-            if let Some(_) = self.reduce_explode(0) {
-                continue;
-            }
-            if self.split_first() {
-                continue;
-            }
-            break;
+
+    /// Applies a single explode or split, if any applies.
+    fn reduce_step(&mut self, rules: &Rules) -> Option<ReductionAction> {
+        let mut position = 0;
+        if let Some(explosion) = self.reduce_explode(0, &mut position, rules) {
+            return Some(ReductionAction::Explode {
+                position,
+                left: explosion.pair.0,
+                right: explosion.pair.1,
+            });
         }
+        let mut position = 0;
+        if let Some(value) = self.split_first(&mut position, rules) {
+            return Some(ReductionAction::Split { position, value });
+        }
+        None
     }
 
-    fn add(self, other: Node) -> Node {
-        let mut result = Node::Pair(Box::from(self), Box::from(other));
-        result.reduce();
-        result
+    fn reduce(&mut self, rules: &Rules) {
+        assert!(rules.split_threshold >= 2, "reduction would not terminate");
+        while let Some(_) = self.reduce_step(rules) {}
     }
 
-    fn magnitude(&self) -> i64 {
+    fn reduce_traced(&mut self, rules: &Rules) -> Vec<ReductionStep> {
+        assert!(rules.split_threshold >= 2, "reduction would not terminate");
+        let mut trace = vec![];
+        while let Some(action) = self.reduce_step(rules) {
+            trace.push(ReductionStep {
+                action,
+                expression: self.to_string(),
+            });
+        }
+        trace
+    }
+
+    fn magnitude(&self, rules: &Rules) -> i64 {
+        let (left_weight, right_weight) = rules.magnitude_weights;
         match self {
             Node::Literal(val) => *val,
-            Node::Pair(left, right) => 3 * left.magnitude() + 2 * right.magnitude(),
+            Node::Pair(left, right) => {
+                left_weight * left.magnitude(rules) + right_weight * right.magnitude(rules)
+            }
         }
     }
+}
 
-    fn expression(&self) -> String {
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Node::Literal(val) => val.to_string(),
-            Node::Pair(left, right) => format!("[{},{}]", left.expression(), right.expression()),
+            Node::Literal(val) => write!(f, "{}", val),
+            Node::Pair(left, right) => write!(f, "[{},{}]", left, right),
+        }
+    }
+}
+
+/// A snailfish number. Sums are always reduced; parsing keeps the number as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnailfishNumber {
+    root: Node,
+}
+
+impl SnailfishNumber {
+    fn magnitude(&self) -> i64 {
+        self.root.magnitude(&PUZZLE_RULES)
+    }
+
+    fn magnitude_with(&self, rules: &Rules) -> i64 {
+        self.root.magnitude(rules)
+    }
+
+    fn add_with(self, other: SnailfishNumber, rules: &Rules) -> SnailfishNumber {
+        let mut root = Node::Pair(Box::from(self.root), Box::from(other.root));
+        root.reduce(rules);
+        SnailfishNumber { root }
+    }
+
+    /// Adds two numbers, returning the sum along with every reduction step.
+    fn add_traced(
+        self,
+        other: SnailfishNumber,
+        rules: &Rules,
+    ) -> (SnailfishNumber, Vec<ReductionStep>) {
+        let mut root = Node::Pair(Box::from(self.root), Box::from(other.root));
+        let trace = root.reduce_traced(rules);
+        (SnailfishNumber { root }, trace)
+    }
+
+    /// Iterates over the regular numbers from left to right, along with the
+    /// number of pairs enclosing each one.
+    fn leaves(&self) -> Leaves<'_> {
+        Leaves {
+            stack: vec![(&self.root, 0)],
+        }
+    }
+}
+
+struct Leaves<'a> {
+    stack: Vec<(&'a Node, usize)>,
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = (i64, usize);
+
+    fn next(&mut self) -> Option<(i64, usize)> {
+        while let Some((node, depth)) = self.stack.pop() {
+            match node {
+                Node::Literal(val) => return Some((*val, depth)),
+                Node::Pair(left, right) => {
+                    self.stack.push((right, depth + 1));
+                    self.stack.push((left, depth + 1));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for SnailfishNumber {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (root, end) = Node::parse(s.as_bytes(), 0)?;
+        if end != s.len() {
+            return Err(ParseError::TrailingInput { position: end });
         }
+        Ok(SnailfishNumber { root })
+    }
+}
+
+impl fmt::Display for SnailfishNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.root.fmt(f)
     }
 }
 
-fn load_expressions() -> Vec<Node> {
+impl Add for SnailfishNumber {
+    type Output = SnailfishNumber;
+
+    fn add(self, other: SnailfishNumber) -> SnailfishNumber {
+        self.add_with(other, &PUZZLE_RULES)
+    }
+}
+
+impl<'a> Add<&'a SnailfishNumber> for &'a SnailfishNumber {
+    type Output = SnailfishNumber;
+
+    fn add(self, other: &SnailfishNumber) -> SnailfishNumber {
+        self.clone() + other.clone()
+    }
+}
+
+/// Panics on an empty iterator, since snailfish addition has no identity.
+impl Sum for SnailfishNumber {
+    fn sum<I: Iterator<Item = SnailfishNumber>>(iter: I) -> SnailfishNumber {
+        iter.reduce(|sum, n| sum + n)
+            .expect("cannot sum an empty list of snailfish numbers")
+    }
+}
+
+/// Leaves deeper than this cannot be represented in a `FlatSnailfishNumber`.
+const FLAT_MAX_DEPTH: usize = 127;
+
+/// A snailfish number stored as its regular numbers from left to right, each
+/// with the number of pairs enclosing it, as yielded by
+/// `SnailfishNumber::leaves`. Reduces with the same rules as the boxed `Node`
+/// without allocating per pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FlatSnailfishNumber {
+    leaves: Vec<(i64, usize)>,
+}
+
+/// Rebuilds the tree shape from the leaf depths, combining each pair of
+/// siblings as soon as both are complete.
+fn fold_leaves<T>(leaves: &[(i64, usize)], leaf: impl Fn(i64) -> T, pair: impl Fn(T, T) -> T) -> T {
+    let mut stack: Vec<(T, usize)> = vec![];
+    for &(value, depth) in leaves {
+        let mut top = (leaf(value), depth);
+        while stack.last().map(|(_, d)| *d) == Some(top.1) {
+            let (left, depth) = stack.pop().unwrap();
+            top = (pair(left, top.0), depth - 1);
+        }
+        stack.push(top);
+    }
+    let (root, depth) = stack.pop().unwrap();
+    assert!(
+        stack.is_empty() && depth == 0,
+        "leaves do not form a single tree"
+    );
+    root
+}
+
+impl FlatSnailfishNumber {
+    fn reduce_explode(&mut self, rules: &Rules) -> Option<ReductionAction> {
+        // Widths are in units where the root is 1 << FLAT_MAX_DEPTH wide, so a
+        // leaf is a left child exactly when its offset is a multiple of its
+        // parent's width.
+        let mut offset: u128 = 0;
+        for i in 0..self.leaves.len() {
+            let (left, depth) = self.leaves[i];
+            let width = 1u128 << (FLAT_MAX_DEPTH - depth);
+            if depth > rules.explode_depth
+                && offset % (2 * width) == 0
+                && self.leaves.get(i + 1).map(|l| l.1) == Some(depth)
+            {
+                let right = self.leaves[i + 1].0;
+                if i > 0 {
+                    self.leaves[i - 1].0 += left;
+                }
+                if let Some(next) = self.leaves.get_mut(i + 2) {
+                    next.0 += right;
+                }
+                self.leaves.splice(i..i + 2, [(0, depth - 1)]);
+                return Some(ReductionAction::Explode {
+                    position: i,
+                    left,
+                    right,
+                });
+            }
+            offset += width;
+        }
+        None
+    }
+
+    fn split_first(&mut self, rules: &Rules) -> Option<ReductionAction> {
+        let position = self
+            .leaves
+            .iter()
+            .position(|(val, _)| *val >= rules.split_threshold)?;
+        let (value, depth) = self.leaves[position];
+        self.leaves.splice(
+            position..position + 1,
+            [(value / 2, depth + 1), (value - value / 2, depth + 1)],
+        );
+        Some(ReductionAction::Split { position, value })
+    }
+
+    fn reduce_step(&mut self, rules: &Rules) -> Option<ReductionAction> {
+        self.reduce_explode(rules)
+            .or_else(|| self.split_first(rules))
+    }
+
+    /// Fails up front if a split could nest a leaf too deeply. Splits only
+    /// happen once nothing explodes, when no leaf is deeper than the
+    /// explode depth, so that is the case exactly when the explode depth
+    /// reaches `FLAT_MAX_DEPTH`.
+    fn reduce(&mut self, rules: &Rules) -> Result<(), TooDeepError> {
+        assert!(rules.split_threshold >= 2, "reduction would not terminate");
+        if rules.explode_depth >= FLAT_MAX_DEPTH {
+            return Err(TooDeepError {
+                depth: rules.explode_depth + 1,
+            });
+        }
+        while let Some(_) = self.reduce_step(rules) {}
+        Ok(())
+    }
+
+    fn magnitude(&self) -> i64 {
+        self.magnitude_with(&PUZZLE_RULES)
+    }
+
+    fn magnitude_with(&self, rules: &Rules) -> i64 {
+        let (left_weight, right_weight) = rules.magnitude_weights;
+        fold_leaves(
+            &self.leaves,
+            |val| val,
+            |left, right| left_weight * left + right_weight * right,
+        )
+    }
+
+    fn add_with(
+        &self,
+        other: &FlatSnailfishNumber,
+        rules: &Rules,
+    ) -> Result<FlatSnailfishNumber, TooDeepError> {
+        let mut result = FlatSnailfishNumber {
+            leaves: self
+                .leaves
+                .iter()
+                .chain(other.leaves.iter())
+                .map(|&(val, depth)| (val, depth + 1))
+                .collect(),
+        };
+        result.reduce(rules)?;
+        Ok(result)
+    }
+}
+
+/// A regular number nested too deeply for a `FlatSnailfishNumber`, either
+/// already or after splitting under the given rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TooDeepError {
+    depth: usize,
+}
+
+/// Rejects leaves at `FLAT_MAX_DEPTH` or deeper, since adding the number to
+/// another one would push them past the deepest representable width.
+impl TryFrom<&SnailfishNumber> for FlatSnailfishNumber {
+    type Error = TooDeepError;
+
+    fn try_from(number: &SnailfishNumber) -> Result<FlatSnailfishNumber, TooDeepError> {
+        let leaves = number.leaves().collect_vec();
+        match leaves.iter().map(|&(_, depth)| depth).max() {
+            Some(depth) if depth >= FLAT_MAX_DEPTH => Err(TooDeepError { depth }),
+            _ => Ok(FlatSnailfishNumber { leaves }),
+        }
+    }
+}
+
+impl From<&FlatSnailfishNumber> for SnailfishNumber {
+    fn from(number: &FlatSnailfishNumber) -> SnailfishNumber {
+        SnailfishNumber {
+            root: fold_leaves(&number.leaves, Node::Literal, |left, right| {
+                Node::Pair(Box::from(left), Box::from(right))
+            }),
+        }
+    }
+}
+
+impl fmt::Display for FlatSnailfishNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        SnailfishNumber::from(self).fmt(f)
+    }
+}
+
+impl<'a> Add<&'a FlatSnailfishNumber> for &'a FlatSnailfishNumber {
+    type Output = FlatSnailfishNumber;
+
+    fn add(self, other: &FlatSnailfishNumber) -> FlatSnailfishNumber {
+        self.add_with(other, &PUZZLE_RULES)
+            .expect("the puzzle rules keep numbers shallow")
+    }
+}
+
+/// The largest magnitude of the sum of any two different numbers, with the
+/// left operands split between threads.
+fn max_pair_magnitude(numbers: &[FlatSnailfishNumber]) -> Option<i64> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    std::thread::scope(|scope| {
+        let handles = (0..threads)
+            .map(|t| {
+                scope.spawn(move || {
+                    (t..numbers.len())
+                        .step_by(threads)
+                        .flat_map(|i| {
+                            (0..numbers.len())
+                                .filter(move |&j| j != i)
+                                .map(move |j| (&numbers[i] + &numbers[j]).magnitude())
+                        })
+                        .max()
+                })
+            })
+            .collect_vec();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap())
+            .max()
+    })
+}
+
+fn load_expressions() -> Vec<SnailfishNumber> {
     let file = File::open("18.txt").unwrap();
     let lines: io::Lines<io::BufReader<File>> = io::BufReader::new(file).lines();
 
-    let expressions = lines
-        .map(|l| Node::parse(l.unwrap().as_bytes(), 0).0)
-        .collect_vec();
+    let expressions = lines.map(|l| l.unwrap().parse().unwrap()).collect_vec();
     return expressions;
 }
 
+fn parse_all(lines: &[&str]) -> Vec<SnailfishNumber> {
+    lines.iter().map(|l| l.parse().unwrap()).collect_vec()
+}
+
 #[test]
 fn part1() {
     let expressions = load_expressions();
-    let sum = expressions.into_iter().reduce(|sum, n| sum.add(n)).unwrap();
-    println!("sum {}", sum.expression());
+    let sum: SnailfishNumber = expressions.into_iter().sum();
+    println!("sum {}", sum);
     println!("magnitude {}", sum.magnitude());
 }
 
 #[test]
 fn part2() {
-    let expressions = load_expressions();
-    let mut max_sum = 0;
-    for i in 0..expressions.len() {
-        for j in 0..expressions.len() {
-            if i != j {
-                let sum = expressions[i]
-                    .clone()
-                    .add(expressions[j].clone())
-                    .magnitude();
-                max_sum = std::cmp::max(sum, max_sum);
-            }
+    let expressions = load_expressions()
+        .iter()
+        .map(FlatSnailfishNumber::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    println!("max sum {}", max_pair_magnitude(&expressions).unwrap());
+}
+
+#[test]
+fn test_parse_display() {
+    let text = "[[[0,[4,5]],[0,0]],[[[4,5],[2,6]],[9,5]]]";
+    let number: SnailfishNumber = text.parse().unwrap();
+    assert_eq!(number.to_string(), text);
+    assert_eq!(
+        "[1,2".parse::<SnailfishNumber>(),
+        Err(ParseError::UnexpectedEnd)
+    );
+    assert_eq!(
+        "[1;2]".parse::<SnailfishNumber>(),
+        Err(ParseError::UnexpectedChar {
+            position: 2,
+            found: ';'
+        })
+    );
+    assert_eq!(
+        "[1,2]]".parse::<SnailfishNumber>(),
+        Err(ParseError::TrailingInput { position: 5 })
+    );
+    assert_eq!(
+        "[1,99999999999999999999]".parse::<SnailfishNumber>(),
+        Err(ParseError::NumberTooLarge { position: 3 })
+    );
+}
+
+#[test]
+fn test_sum_intermediate() {
+    let numbers = parse_all(&["[1,1]", "[2,2]", "[3,3]", "[4,4]", "[5,5]", "[6,6]"]);
+    let partial_sums = numbers
+        .into_iter()
+        .scan(None, |sum: &mut Option<SnailfishNumber>, n| {
+            let next = match sum.take() {
+                Some(s) => s + n,
+                None => n,
+            };
+            *sum = Some(next.clone());
+            Some(next.to_string())
+        })
+        .skip(3)
+        .collect_vec();
+    assert_eq!(
+        partial_sums,
+        [
+            "[[[[1,1],[2,2]],[3,3]],[4,4]]",
+            "[[[[3,0],[5,3]],[4,4]],[5,5]]",
+            "[[[[5,0],[7,4]],[5,5]],[6,6]]",
+        ]
+    );
+    let sum = parse_all(&["[[[[4,3],4],4],[7,[[8,4],9]]]", "[1,1]"])
+        .into_iter()
+        .sum::<SnailfishNumber>();
+    assert_eq!(sum.to_string(), "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]");
+}
+
+#[test]
+fn test_homework() {
+    let numbers = parse_all(&[
+        "[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]",
+        "[[[5,[2,8]],4],[5,[[9,9],0]]]",
+        "[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]",
+        "[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]",
+        "[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]",
+        "[[6,[[7,3],[3,2]]],[[[3,8],[5,7]],4]]",
+        "[[[[5,4],[7,7]],8],[[8,3],8]]",
+        "[[9,3],[[9,9],[6,[4,9]]]]",
+        "[[2,[[7,7],7]],[[5,8],[[9,3],[0,2]]]]",
+        "[[[[5,2],5],[8,[3,7]]],[[5,[7,5]],[4,4]]]",
+    ]);
+    let sum: SnailfishNumber = numbers.iter().cloned().sum();
+    assert_eq!(
+        sum.to_string(),
+        "[[[[6,6],[7,6]],[[7,7],[7,0]]],[[[7,7],[7,7]],[[7,8],[9,9]]]]"
+    );
+    assert_eq!(sum.magnitude(), 4140);
+    let max_pair = numbers
+        .iter()
+        .permutations(2)
+        .map(|pair| (pair[0] + pair[1]).magnitude())
+        .max();
+    assert_eq!(max_pair, Some(3993));
+}
+
+#[test]
+fn test_leaves() {
+    let number: SnailfishNumber = "[[1,2],[[3,4],5]]".parse().unwrap();
+    assert_eq!(
+        number.leaves().collect_vec(),
+        [(1, 2), (2, 2), (3, 3), (4, 3), (5, 2)]
+    );
+    assert_eq!(number.magnitude(), 143);
+}
+
+#[test]
+fn test_reduction_trace() {
+    let a: SnailfishNumber = "[[[[4,3],4],4],[7,[[8,4],9]]]".parse().unwrap();
+    let b: SnailfishNumber = "[1,1]".parse().unwrap();
+    let (sum, trace) = a.add_traced(b, &PUZZLE_RULES);
+    assert_eq!(
+        trace.iter().map(|step| step.action.clone()).collect_vec(),
+        [
+            ReductionAction::Explode {
+                position: 0,
+                left: 4,
+                right: 3
+            },
+            ReductionAction::Explode {
+                position: 4,
+                left: 8,
+                right: 4
+            },
+            ReductionAction::Split {
+                position: 3,
+                value: 15
+            },
+            ReductionAction::Split {
+                position: 6,
+                value: 13
+            },
+            ReductionAction::Explode {
+                position: 6,
+                left: 6,
+                right: 7
+            },
+        ]
+    );
+    assert_eq!(
+        trace.iter().map(|step| step.to_string()).collect_vec(),
+        [
+            "after explode:  [[[[0,7],4],[7,[[8,4],9]]],[1,1]]",
+            "after explode:  [[[[0,7],4],[15,[0,13]]],[1,1]]",
+            "after split:    [[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+            "after split:    [[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+            "after explode:  [[[[0,7],4],[[7,8],[6,0]]],[8,1]]",
+        ]
+    );
+    assert_eq!(sum.to_string(), trace.last().unwrap().expression);
+}
+
+#[test]
+fn test_flat_matches_boxed() {
+    let numbers = parse_all(&[
+        "[[[0,[5,8]],[[1,7],[9,6]]],[[4,[1,2]],[[1,4],2]]]",
+        "[[[5,[2,8]],4],[5,[[9,9],0]]]",
+        "[6,[[[6,2],[5,6]],[[7,6],[4,7]]]]",
+        "[[[6,[0,7]],[0,9]],[4,[9,[9,0]]]]",
+        "[[[7,[6,4]],[3,[1,3]]],[[[5,5],1],9]]",
+        "[[6,[[7,3],[3,2]]],[[[3,8],[5,7]],4]]",
+        "[[[[5,4],[7,7]],8],[[8,3],8]]",
+        "[[9,3],[[9,9],[6,[4,9]]]]",
+        "[[2,[[7,7],7]],[[5,8],[[9,3],[0,2]]]]",
+        "[[[[5,2],5],[8,[3,7]]],[[5,[7,5]],[4,4]]]",
+    ]);
+    let flat = numbers
+        .iter()
+        .map(|number| FlatSnailfishNumber::try_from(number).unwrap())
+        .collect_vec();
+    for (number, flat_number) in numbers.iter().zip(&flat) {
+        assert_eq!(&SnailfishNumber::from(flat_number), number);
+        assert_eq!(flat_number.magnitude(), number.magnitude());
+    }
+    for (a, b) in numbers.iter().zip(&flat).tuple_windows() {
+        let boxed_sum = a.0 + b.0;
+        let flat_sum = a.1 + b.1;
+        assert_eq!(flat_sum.to_string(), boxed_sum.to_string());
+        assert_eq!(flat_sum.magnitude(), boxed_sum.magnitude());
+    }
+    assert_eq!(max_pair_magnitude(&flat), Some(3993));
+
+    let depth = FLAT_MAX_DEPTH;
+    let deep: SnailfishNumber = (1..depth)
+        .fold("[1,2]".to_string(), |inner, _| format!("[{},0]", inner))
+        .parse()
+        .unwrap();
+    assert_eq!(
+        FlatSnailfishNumber::try_from(&deep),
+        Err(TooDeepError { depth })
+    );
+
+    // Rules that never explode would let splits run past the deepest width.
+    let ten = FlatSnailfishNumber::try_from(&"[10,0]".parse().unwrap()).unwrap();
+    let rules = |explode_depth| Rules {
+        explode_depth,
+        ..PUZZLE_RULES
+    };
+    assert_eq!(
+        ten.add_with(&ten, &rules(depth)),
+        Err(TooDeepError { depth: depth + 1 })
+    );
+    assert_eq!(
+        ten.add_with(&ten, &rules(depth - 1)).unwrap().to_string(),
+        "[[[5,5],0],[[5,5],0]]"
+    );
+}
+
+#[test]
+fn test_flat_reduction_steps() {
+    // 1 is nested inside five pairs, but its sibling is not a regular number,
+    // so [2,3] must explode first.
+    let mut node = Node::parse(b"[[[[[1,[2,3]],4],5],6],[[7,8],[9,[10,11]]]]", 0)
+        .unwrap()
+        .0;
+    let mut flat = FlatSnailfishNumber::try_from(&SnailfishNumber { root: node.clone() }).unwrap();
+    loop {
+        let action = node.reduce_step(&PUZZLE_RULES);
+        assert_eq!(flat.reduce_step(&PUZZLE_RULES), action);
+        assert_eq!(flat.to_string(), node.to_string());
+        if action.is_none() {
+            break;
+        }
+    }
+}
+
+/// A random reduced number under `rules`, with the root at `depth`.
+#[cfg(test)]
+fn random_node(rng: &mut crate::Lcg, depth: usize, rules: &Rules) -> Node {
+    if depth == rules.explode_depth || (depth > 0 && rng.range(0, 2) == 0) {
+        Node::Literal(rng.range(0, rules.split_threshold - 1))
+    } else {
+        Node::Pair(
+            Box::from(random_node(rng, depth + 1, rules)),
+            Box::from(random_node(rng, depth + 1, rules)),
+        )
+    }
+}
+
+#[test]
+fn test_rules_invariants() {
+    let variants = [
+        PUZZLE_RULES,
+        Rules {
+            explode_depth: 2,
+            split_threshold: 5,
+            magnitude_weights: (1, 1),
+        },
+        Rules {
+            explode_depth: 6,
+            split_threshold: 20,
+            magnitude_weights: (5, 7),
+        },
+        Rules {
+            explode_depth: 3,
+            split_threshold: 2,
+            magnitude_weights: (2, 3),
+        },
+    ];
+    let mut rng = crate::Lcg::new(18);
+    for rules in &variants {
+        for _ in 0..200 {
+            let a = SnailfishNumber {
+                root: random_node(&mut rng, 0, rules),
+            };
+            let b = SnailfishNumber {
+                root: random_node(&mut rng, 0, rules),
+            };
+            let flat_a = FlatSnailfishNumber::try_from(&a).unwrap();
+            let flat_b = FlatSnailfishNumber::try_from(&b).unwrap();
+            let flat_sum = flat_a.add_with(&flat_b, rules).unwrap();
+            let sum = a.add_with(b, rules);
+            assert!(sum
+                .leaves()
+                .all(|(val, depth)| depth <= rules.explode_depth && val < rules.split_threshold));
+            assert_eq!(flat_sum.to_string(), sum.to_string());
+            assert_eq!(flat_sum.magnitude_with(rules), sum.magnitude_with(rules));
         }
     }
-    println!("max sum {}", max_sum);
 }
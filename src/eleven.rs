@@ -1,7 +1,11 @@
+use crate::automaton::{
+    find_repeat, hash_state, Automaton, Boundary, Cascade, Cycle, Neighborhood, Update,
+};
 use array2d::Array2D;
 use itertools::Itertools;
 use std::fs::File;
 use std::io::{self, BufRead};
+use std::rc::Rc;
 
 fn load_array() -> Array2D<i64> {
     let file = File::open("11.txt").unwrap();
@@ -19,41 +23,62 @@ fn load_array() -> Array2D<i64> {
     );
 }
 
+/// The rules the octopuses follow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OctopusRules {
+    /// An octopus flashes once its energy goes above this.
+    threshold: i64,
+    /// The octopuses that gain energy when one flashes.
+    neighborhood: Neighborhood,
+}
+
+impl Default for OctopusRules {
+    fn default() -> OctopusRules {
+        OctopusRules {
+            threshold: 9,
+            neighborhood: Neighborhood::moore(1),
+        }
+    }
+}
+
+#[cfg(test)]
 fn apply_step(array: &mut Array2D<i64>) -> i64 {
+    apply_step_with(array, &OctopusRules::default()).len() as i64
+}
+
+/// Runs one step, returning the octopuses that flashed in the order they
+/// flashed. Kept as a reference for `octopus_automaton`.
+#[cfg(test)]
+fn apply_step_with(array: &mut Array2D<i64>, rules: &OctopusRules) -> Vec<(usize, usize)> {
     let mut popped = Array2D::filled_with(false, array.num_rows(), array.num_columns());
     let mut stack: Vec<(usize, usize)> = vec![];
     for i in 0..array.num_rows() {
         for j in 0..array.num_columns() {
             let val = array.get_mut(i, j).unwrap();
             *val += 1;
-            if *val > 9 {
+            if *val > rules.threshold {
                 stack.push((i, j));
                 popped.set(i, j, true).unwrap();
             }
         }
     }
-    let mut num_pops = 0;
+    let mut order = vec![];
     loop {
         match stack.pop() {
             Some((i, j)) => {
-                num_pops += 1;
-                for di in -1i64..=1 {
-                    for dj in -1i64..=1 {
-                        if (di, dj) == (0, 0) {
-                            continue;
-                        }
-                        // Now we POP
-                        let (ni, nj) = ((i as i64 + di) as usize, (j as i64 + dj) as usize);
-                        match array.get_mut(ni, nj) {
-                            Some(neighbor_ref) => {
-                                *neighbor_ref += 1;
-                                if *neighbor_ref > 9 && !popped[(ni, nj)] {
-                                    stack.push((ni, nj));
-                                    popped.set(ni, nj, true).unwrap();
-                                }
+                order.push((i, j));
+                for [di, dj] in &rules.neighborhood.offsets {
+                    // Now we POP
+                    let (ni, nj) = ((i as i64 + di) as usize, (j as i64 + dj) as usize);
+                    match array.get_mut(ni, nj) {
+                        Some(neighbor_ref) => {
+                            *neighbor_ref += 1;
+                            if *neighbor_ref > rules.threshold && !popped[(ni, nj)] {
+                                stack.push((ni, nj));
+                                popped.set(ni, nj, true).unwrap();
                             }
-                            None => {},
                         }
+                        None => {},
                     }
                 }
             }
@@ -65,31 +90,255 @@ fn apply_step(array: &mut Array2D<i64>) -> i64 {
     for i in 0..array.num_rows() {
         for j in 0..array.num_columns() {
             let val = array.get_mut(i, j).unwrap();
-            if *val > 9 {
+            if *val > rules.threshold {
                 *val = 0;
             }
         }
     }
-    return num_pops;
+    return order;
+}
+
+/// What happened in one step of a `Simulation`.
+#[derive(Debug, Clone)]
+struct StepRecord {
+    /// Which octopuses flashed.
+    flashed: Array2D<bool>,
+    /// The octopuses that flashed, in the order the cascade reached them.
+    order: Vec<(usize, usize)>,
+}
+
+/// Steps the octopuses' automaton, optionally keeping a record of every
+/// step.
+#[derive(Clone)]
+struct Simulation {
+    automaton: Automaton<i64>,
+    steps: usize,
+    total_flashes: usize,
+    first_synchronized: Option<usize>,
+    /// Every step so far, if the simulation was asked to keep them.
+    history: Option<Vec<StepRecord>>,
+}
+
+impl Simulation {
+    fn new(array: &Array2D<i64>, rules: OctopusRules, record_history: bool) -> Simulation {
+        Simulation {
+            automaton: octopus_automaton(array, &rules),
+            steps: 0,
+            total_flashes: 0,
+            first_synchronized: None,
+            history: record_history.then(Vec::new),
+        }
+    }
+
+    fn step(&mut self) -> StepRecord {
+        self.automaton.step();
+        let order = self.automaton.fired().to_vec();
+        let grid = &self.automaton.grid;
+        let mut flashed = Array2D::filled_with(false, grid.num_rows(), grid.num_columns());
+        for position in &order {
+            flashed[*position] = true;
+        }
+        self.steps += 1;
+        self.total_flashes += order.len();
+        if order.len() == grid.num_elements() && self.first_synchronized.is_none() {
+            self.first_synchronized = Some(self.steps);
+        }
+        let record = StepRecord { flashed, order };
+        if let Some(history) = &mut self.history {
+            history.push(record.clone());
+        }
+        record
+    }
+
+    fn total_flashes(&self) -> usize {
+        self.total_flashes
+    }
+
+    /// The first step so far, counting from 1, in which every octopus
+    /// flashed.
+    fn first_synchronized(&self) -> Option<usize> {
+        self.first_synchronized
+    }
+
+    /// Steps until every octopus flashes at once, giving up after
+    /// `max_steps` more steps.
+    fn run_until_synchronized(&mut self, max_steps: usize) -> Option<usize> {
+        for _ in 0..max_steps {
+            if self.first_synchronized.is_some() {
+                break;
+            }
+            self.step();
+        }
+        self.first_synchronized
+    }
+
+    /// Steps until the energy levels repeat, giving up after `max_steps`
+    /// more steps.
+    fn find_cycle(&mut self, max_steps: usize) -> Option<Cycle> {
+        let start = self.steps;
+        find_repeat(
+            self,
+            max_steps,
+            |simulation| {
+                simulation.step();
+            },
+            |simulation| hash_state(&simulation.automaton.grid.as_row_major()),
+            |a, b| a.automaton.grid == b.automaton.grid,
+        )
+        .map(|cycle| Cycle {
+            start: start + cycle.start,
+            ..cycle
+        })
+    }
+}
+
+/// The octopuses as a cascading automaton.
+fn octopus_automaton(array: &Array2D<i64>, rules: &OctopusRules) -> Automaton<i64> {
+    let threshold = rules.threshold;
+    Automaton::new(
+        array.clone(),
+        Boundary::Finite,
+        Update::Cascading(Cascade {
+            neighborhood: rules.neighborhood.clone(),
+            charge: Rc::new(|e| e + 1),
+            fires: Rc::new(move |e| *e > threshold),
+            receive: Rc::new(|e| e + 1),
+            settle: Rc::new(move |e| if *e > threshold { 0 } else { *e }),
+        }),
+    )
 }
+
 #[test]
 fn part1() {
-    let mut array = load_array();
-    let mut num_flashes = 0;
-    for _ in 0..100 {
-        num_flashes += apply_step(&mut array);
-    }
+    let mut automaton = octopus_automaton(&load_array(), &OctopusRules::default());
+    let num_flashes: usize = (0..100).map(|_| automaton.step()).sum();
     println!("num flashes {}", num_flashes);
 }
 
 #[test]
 fn part2() {
+    let mut simulation = Simulation::new(&load_array(), OctopusRules::default(), false);
+    match simulation.run_until_synchronized(10000000) {
+        Some(step) => println!("first synchronized at step {}", step),
+        None => println!("never synchronized"),
+    }
+    println!("cycle {:?}", simulation.find_cycle(1000));
+}
+
+#[test]
+fn test_octopus_automaton() {
+    // The automaton against the original step, flash order included.
     let mut array = load_array();
-    for i in 0..10000000 {
-        let flashes = apply_step(&mut array);
-        if flashes == array.num_elements() as i64 {
-            println!("Found after step {}", i);
-            return;
-        }
+    let mut automaton = octopus_automaton(&array, &OctopusRules::default());
+    for _ in 0..200 {
+        assert_eq!(automaton.step(), apply_step(&mut array) as usize);
+        assert_eq!(automaton.grid, array);
+    }
+    let rules = OctopusRules {
+        threshold: 5,
+        neighborhood: Neighborhood::von_neumann(1),
+    };
+    let mut array = load_array();
+    let mut automaton = octopus_automaton(&array, &rules);
+    for _ in 0..200 {
+        automaton.step();
+        assert_eq!(automaton.fired(), apply_step_with(&mut array, &rules));
+        assert_eq!(automaton.grid, array);
+    }
+}
+
+#[test]
+fn test_simulation_history() {
+    let example = [
+        [5, 4, 8, 3, 1, 4, 3, 2, 2, 3],
+        [2, 7, 4, 5, 8, 5, 4, 7, 1, 1],
+        [5, 2, 6, 4, 5, 5, 6, 1, 7, 3],
+        [6, 1, 4, 1, 3, 3, 6, 1, 4, 6],
+        [6, 3, 5, 7, 3, 8, 5, 4, 7, 8],
+        [4, 1, 6, 7, 5, 2, 4, 6, 4, 5],
+        [2, 1, 7, 6, 8, 4, 1, 7, 2, 1],
+        [6, 8, 8, 2, 8, 8, 1, 1, 3, 4],
+        [4, 8, 4, 6, 8, 4, 8, 5, 5, 4],
+        [5, 2, 8, 3, 7, 5, 1, 5, 2, 6],
+    ];
+    let array = Array2D::from_rows(&example.iter().map(|r| r.to_vec()).collect_vec());
+    let mut simulation = Simulation::new(&array, OctopusRules::default(), true);
+    for _ in 0..100 {
+        simulation.step();
+    }
+    assert_eq!(simulation.total_flashes(), 1656);
+    assert_eq!(simulation.first_synchronized(), None);
+    assert_eq!(simulation.run_until_synchronized(1000), Some(195));
+    assert_eq!(simulation.first_synchronized(), Some(195));
+    // Once synchronized, every octopus has the same energy, so they keep
+    // flashing together every ten steps.
+    assert_eq!(
+        simulation.find_cycle(100),
+        Some(Cycle {
+            start: 195,
+            period: 10
+        })
+    );
+
+    let history = simulation.history.unwrap();
+    assert_eq!(history.len(), 205);
+    for record in &history {
+        assert_eq!(
+            record
+                .flashed
+                .elements_row_major_iter()
+                .filter(|f| **f)
+                .count(),
+            record.order.len()
+        );
+    }
+
+    // Without a history the counts are the same.
+    let mut simulation = Simulation::new(&array, OctopusRules::default(), false);
+    assert_eq!(simulation.run_until_synchronized(1000), Some(195));
+    assert_eq!(simulation.history.map(|h| h.len()), None);
+    assert_eq!(simulation.steps, 195);
+
+    // The ring flashes first, last one found first, and only then does the
+    // center collect enough energy to flash.
+    let ring = Array2D::from_rows(&[
+        vec![1, 1, 1, 1, 1],
+        vec![1, 9, 9, 9, 1],
+        vec![1, 9, 1, 9, 1],
+        vec![1, 9, 9, 9, 1],
+        vec![1, 1, 1, 1, 1],
+    ]);
+    let mut simulation = Simulation::new(&ring, OctopusRules::default(), false);
+    assert_eq!(
+        simulation.step().order,
+        [
+            (3, 3),
+            (3, 2),
+            (3, 1),
+            (2, 3),
+            (2, 1),
+            (1, 3),
+            (1, 2),
+            (1, 1),
+            (2, 2)
+        ]
+    );
+
+    // A lone octopus with threshold 1 flashes every second step, and
+    // nothing is next to it in either neighborhood.
+    for neighborhood in [Neighborhood::moore(1), Neighborhood::von_neumann(1)] {
+        let rules = OctopusRules {
+            threshold: 1,
+            neighborhood,
+        };
+        let mut simulation = Simulation::new(&Array2D::from_rows(&[vec![0]]), rules, false);
+        assert_eq!(simulation.run_until_synchronized(10), Some(2));
+        assert_eq!(
+            simulation.find_cycle(10),
+            Some(Cycle {
+                start: 2,
+                period: 2
+            })
+        );
     }
 }
@@ -2,6 +2,7 @@
 #![feature(slice_pattern)]
 #![feature(array_zip)]
 #![feature(int_abs_diff)]
+mod automaton;
 // mod eight;
 mod eighteen;
 mod eleven;
@@ -27,3 +28,27 @@ mod twentytwo;
 mod two;
 mod twentyfour;
 mod twentyfive;
+
+/// A seeded linear congruential generator, so random tests and dice are
+/// reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn new(seed: u64) -> Lcg {
+        Lcg(seed)
+    }
+
+    /// The high 31 bits of the next state.
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    /// A number in `lower..=upper`.
+    fn range(&mut self, lower: i64, upper: i64) -> i64 {
+        lower + (self.next() % (upper - lower + 1) as u64) as i64
+    }
+}
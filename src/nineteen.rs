@@ -1,7 +1,7 @@
 use itertools::{iproduct, Itertools};
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Write};
 
 #[derive(Debug)]
 struct Scanner {
@@ -13,158 +13,114 @@ trait Transform {
     fn inverse(&self) -> Self;
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Reflection {
-    inversion: [bool; 3],
-}
-
-// Technically a permutation of axes, not necessarily a rotation
-
-#[derive(Debug, Clone, Copy)]
+/// A proper rotation, stored as a 3x3 signed permutation matrix with
+/// determinant 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Rotation {
-    permutation: [i8; 3],
-}
-
-#[derive(Debug, Clone, Copy)]
-struct Translation {
-    offset: [i64; 3],
+    matrix: [[i64; 3]; 3],
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Combination {
-    rev: bool,
-    reflection: Reflection,
+/// A rotation followed by a translation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Affine {
     rotation: Rotation,
-    translation: Translation,
+    translation: [i64; 3],
 }
 
-impl Transform for Reflection {
+impl Transform for Rotation {
     fn apply(&self, point: &[i64; 3]) -> [i64; 3] {
-        [
-            if self.inversion[0] {
-                -point[0]
-            } else {
-                point[0]
-            },
-            if self.inversion[1] {
-                -point[1]
-            } else {
-                point[1]
-            },
-            if self.inversion[2] {
-                -point[2]
-            } else {
-                point[2]
-            },
-        ]
+        self.matrix
+            .map(|row| row.zip(*point).iter().map(|(a, b)| a * b).sum())
     }
     fn inverse(&self) -> Self {
-        *self
+        let mut transpose = [[0; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                transpose[j][i] = self.matrix[i][j];
+            }
+        }
+        Rotation { matrix: transpose }
     }
 }
 
-impl Transform for Rotation {
-    fn apply(&self, point: &[i64; 3]) -> [i64; 3] {
-        let mut result = [0; 3];
-        for i in 0..3 {
-            result[self.permutation[i] as usize] = point[i];
+impl Rotation {
+    fn id() -> Rotation {
+        Rotation {
+            matrix: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
         }
-        result
     }
-    fn inverse(&self) -> Self {
-        let mut inverse = [0i8; 3];
-        for i in 0..3i8 {
-            inverse[self.permutation[i as usize] as usize] = i;
-        }
-        Rotation {
-            permutation: inverse,
+
+    /// The rotation applying `other` first, then `self`.
+    fn compose(&self, other: &Rotation) -> Rotation {
+        let mut matrix = [[0; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                matrix[i][j] = (0..3).map(|k| self.matrix[i][k] * other.matrix[k][j]).sum();
+            }
         }
+        Rotation { matrix }
     }
-}
 
-impl Transform for Translation {
-    fn apply(&self, point: &[i64; 3]) -> [i64; 3] {
-        point.zip(self.offset).map(|(a, b)| a + b)
+    fn determinant(&self) -> i64 {
+        let m = &self.matrix;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
     }
-    fn inverse(&self) -> Self {
-        Translation {
-            offset: self.offset.map(|x| -x),
-        }
+
+    /// All 24 orientations of a scanner.
+    fn all() -> impl Iterator<Item = Rotation> {
+        let signs = [1, -1];
+        [0usize, 1, 2]
+            .into_iter()
+            .permutations(3)
+            .cartesian_product(iproduct!(signs, signs, signs))
+            .map(|(permutation, (sx, sy, sz))| {
+                let mut matrix = [[0; 3]; 3];
+                for (row, sign) in [sx, sy, sz].into_iter().enumerate() {
+                    matrix[row][permutation[row]] = sign;
+                }
+                Rotation { matrix }
+            })
+            .filter(|rotation| rotation.determinant() == 1)
     }
 }
 
-impl Transform for Combination {
+impl Transform for Affine {
     fn apply(&self, point: &[i64; 3]) -> [i64; 3] {
-        if self.rev {
-            self.reflection
-                .apply(&self.rotation.apply(&self.translation.apply(point)))
-        } else {
-            self.translation
-                .apply(&self.rotation.apply(&self.reflection.apply(point)))
-        }
+        self.rotation
+            .apply(point)
+            .zip(self.translation)
+            .map(|(a, b)| a + b)
     }
     fn inverse(&self) -> Self {
-        Combination {
-            rev: !self.rev,
-            reflection: self.reflection.inverse(),
-            rotation: self.rotation.inverse(),
-            translation: self.translation.inverse(),
+        let rotation = self.rotation.inverse();
+        Affine {
+            rotation,
+            translation: rotation.apply(&self.translation).map(|x| -x),
         }
     }
 }
 
-impl Combination {
-    fn new(reflection: [bool; 3], rotation: [i8; 3], translation: [i64; 3]) -> Combination {
-        Combination {
-            rev: false,
-            reflection: Reflection {
-                inversion: reflection,
-            },
-            rotation: Rotation {
-                permutation: rotation,
-            },
-            translation: Translation {
-                offset: translation,
-            },
+impl Affine {
+    fn new(rotation: Rotation, translation: [i64; 3]) -> Affine {
+        Affine {
+            rotation,
+            translation,
         }
     }
-}
-impl Translation {
-    fn id() -> Translation {
-        Translation { offset: [0, 0, 0] }
+
+    fn id() -> Affine {
+        Affine::new(Rotation::id(), [0, 0, 0])
     }
-}
 
-fn permutation_parity(permutation: &[i8; 3]) -> bool {
-    permutation
-        .iter()
-        .enumerate()
-        .filter(|(i, v)| *i as i8 == **v)
-        .count()
-        == 1
-}
-
-fn all_symmetries() -> impl Iterator<Item = (Reflection, Rotation)> {
-    let false_true = [false, true];
-    let all_permutations = [
-        [0, 1, 2],
-        [0, 2, 1],
-        [1, 0, 2],
-        [1, 2, 0],
-        [2, 0, 1],
-        [2, 1, 0],
-    ];
-    return iproduct!(false_true, false_true, all_permutations.into_iter()).map(
-        |(invx, invy, permutation)| {
-            let parity = invx ^ invy ^ permutation_parity(&permutation);
-            (
-                Reflection {
-                    inversion: [invx, invy, parity],
-                },
-                Rotation { permutation },
-            )
-        },
-    );
+    /// The transform applying `other` first, then `self`.
+    fn compose(&self, other: &Affine) -> Affine {
+        Affine {
+            rotation: self.rotation.compose(&other.rotation),
+            translation: self.apply(&other.translation),
+        }
+    }
 }
 
 fn in_bounds(point: &[i64; 3], min_bounds: &[i64; 3], max_bounds: &[i64; 3]) -> bool {
@@ -175,67 +131,212 @@ fn in_bounds(point: &[i64; 3], min_bounds: &[i64; 3], max_bounds: &[i64; 3]) ->
         .all(|((p, lower), upper)| p >= lower && p <= upper)
 }
 
+/// How closely two scanners must agree to be considered overlapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AlignmentConfig {
+    /// How far a scanner can see along each axis.
+    size: i64,
+    /// Beacons the two scanners must have in common.
+    min_overlap: i64,
+    /// Fraction of the beacons in the region both scanners can see that may be
+    /// reported by only one of them.
+    max_mismatch_fraction: f64,
+    /// How far, along each axis, a scanner's report of a beacon may be from
+    /// where the beacon really is.
+    tolerance: i64,
+}
+
+const PUZZLE_ALIGNMENT: AlignmentConfig = AlignmentConfig {
+    size: 1000,
+    min_overlap: 12,
+    max_mismatch_fraction: 0.0,
+    tolerance: 0,
+};
+
+impl Default for AlignmentConfig {
+    fn default() -> AlignmentConfig {
+        PUZZLE_ALIGNMENT
+    }
+}
+
+/// An accepted transform between two scanners, and how well they agree under it.
+#[derive(Debug, Clone, PartialEq)]
+struct Alignment {
+    /// Takes points of the second scanner into the frame of the first.
+    transform: Affine,
+    /// Beacons in the shared region reported by both scanners.
+    matched: usize,
+    /// Beacons in the shared region reported by only one scanner.
+    mismatched: usize,
+    /// For each matched beacon, its position according to the first scanner
+    /// minus its transformed position according to the second.
+    residuals: Vec<[i64; 3]>,
+}
+
+impl Alignment {
+    /// The same alignment, seen from the second scanner.
+    fn inverse(&self) -> Alignment {
+        let rotation = self.transform.rotation.inverse();
+        Alignment {
+            transform: self.transform.inverse(),
+            residuals: self
+                .residuals
+                .iter()
+                .map(|r| rotation.apply(r).map(|x| -x))
+                .collect(),
+            ..*self
+        }
+    }
+
+    fn rms_residual(&self) -> f64 {
+        if self.residuals.is_empty() {
+            return 0.0;
+        }
+        let total: i64 = self
+            .residuals
+            .iter()
+            .map(|r| r.iter().map(|x| x * x).sum::<i64>())
+            .sum();
+        (total as f64 / self.residuals.len() as f64).sqrt()
+    }
+}
+
 impl Scanner {
+    /// Checks how well `transform`, taking points of `other` into the frame of
+    /// `self`, makes both scanners agree on the beacons in the region they can
+    /// both see. `point_set` holds the points of `self`.
+    ///
+    /// With a nonzero tolerance, the translation is refined to the mean
+    /// residual of a first, looser matching before the final one, since the
+    /// correspondence it came from may itself be off by twice the tolerance.
+    fn align(
+        &self,
+        point_set: &HashSet<&[i64; 3]>,
+        other: &Scanner,
+        transform: &Affine,
+        config: &AlignmentConfig,
+    ) -> Option<Alignment> {
+        if config.tolerance == 0 {
+            return self.align_exact(point_set, other, transform, config);
+        }
+        let rough = self.align_within(other, transform, 4 * config.tolerance, config)?;
+        let count = rough.residuals.len() as f64;
+        let shift = [0, 1, 2].map(|axis| {
+            let total: i64 = rough.residuals.iter().map(|r| r[axis]).sum();
+            (total as f64 / count).round() as i64
+        });
+        let refined = Affine::new(
+            transform.rotation,
+            transform.translation.zip(shift).map(|(t, s)| t + s),
+        );
+        self.align_within(other, &refined, 2 * config.tolerance, config)
+    }
+
+    /// The points of `self` in the region it shares with a scanner at
+    /// `offset`, along with the bounds of that region.
+    fn shared_region(&self, offset: &[i64; 3], size: i64) -> (Vec<&[i64; 3]>, [i64; 3], [i64; 3]) {
+        let min_bounds = offset.map(|c| std::cmp::max(-size, c - size));
+        let max_bounds = offset.map(|c| std::cmp::min(size, c + size));
+        let shared_points = self
+            .points
+            .iter()
+            .filter(|p| in_bounds(p, &min_bounds, &max_bounds))
+            .collect_vec();
+        (shared_points, min_bounds, max_bounds)
+    }
+
+    fn align_exact(
+        &self,
+        point_set: &HashSet<&[i64; 3]>,
+        other: &Scanner,
+        transform: &Affine,
+        config: &AlignmentConfig,
+    ) -> Option<Alignment> {
+        let (shared_points, min_bounds, max_bounds) =
+            self.shared_region(&transform.translation, config.size);
+        // Until every point of `other` is seen, only an upper bound on the
+        // allowed mismatches is known.
+        let early_limit = max_mismatched(config, shared_points.len() + other.points.len());
+
+        let mut residuals = vec![];
+        let mut shared_other = 0;
+        for p in &other.points {
+            let transformed_point = transform.apply(p);
+            if !in_bounds(&transformed_point, &min_bounds, &max_bounds) {
+                continue;
+            }
+            shared_other += 1;
+            if point_set.contains(&transformed_point) {
+                residuals.push([0; 3]);
+            } else if shared_other - residuals.len() > early_limit {
+                return None;
+            }
+        }
+        accept_alignment(
+            config,
+            transform,
+            shared_points.len() + shared_other,
+            residuals,
+        )
+    }
+
+    /// Pairs up the beacons of both scanners that are at most `window` apart
+    /// on every axis, closest pairs first, so each beacon gets its nearest
+    /// counterpart that is not already taken.
+    fn align_within(
+        &self,
+        other: &Scanner,
+        transform: &Affine,
+        window: i64,
+        config: &AlignmentConfig,
+    ) -> Option<Alignment> {
+        let (shared_points, min_bounds, max_bounds) =
+            self.shared_region(&transform.translation, config.size);
+        let shared_other = other
+            .points
+            .iter()
+            .map(|p| transform.apply(p))
+            .filter(|p| in_bounds(p, &min_bounds, &max_bounds))
+            .collect_vec();
+        let mut pairs = iproduct!(
+            shared_points.iter().enumerate(),
+            shared_other.iter().enumerate()
+        )
+        .filter(|((_, p), (_, q))| p.zip(**q).iter().all(|(a, b)| (a - b).abs() <= window))
+        .map(|((i, p), (j, q))| (squared_distance(p, q), i, j))
+        .collect_vec();
+        pairs.sort();
+        let mut used = vec![false; shared_points.len()];
+        let mut other_used = vec![false; shared_other.len()];
+        let mut residuals = vec![];
+        for (_, i, j) in pairs {
+            if !used[i] && !other_used[j] {
+                used[i] = true;
+                other_used[j] = true;
+                residuals.push(shared_points[i].zip(shared_other[j]).map(|(a, b)| a - b));
+            }
+        }
+        accept_alignment(
+            config,
+            transform,
+            shared_points.len() + shared_other.len(),
+            residuals,
+        )
+    }
+
     //  Returns the translation to apply to other to match with self.
-    fn matches(&self, other: &Scanner, size: i64, min_overlap: i64) -> Option<Combination> {
+    fn matches(&self, other: &Scanner, config: &AlignmentConfig) -> Option<Alignment> {
         let point_set: HashSet<_> = self.points.iter().collect();
-        for (reflection, rotation) in all_symmetries() {
-            let rotation = Combination {
-                rev: false,
-                reflection,
-                rotation,
-                translation: Translation::id(),
-            };
+        for rotation in Rotation::all() {
             // println!("Rotation {:?}", rotation);
             for point in &self.points {
                 for other_point in &other.points {
                     let offset = point.zip(rotation.apply(&other_point)).map(|(p, o)| p - o);
                     // println!("offset {:?}", offset);
                     // println!("points {:?} {:?}", point, other_point);
-                    let min_bounds = offset.map(|c| std::cmp::max(-size, c - size));
-                    let max_bounds = offset.map(|c| std::cmp::min(size, c + size));
-                    // println!("bounds {:?}, {:?}", min_bounds, max_bounds);
-                    let transform = Combination {
-                        translation: Translation { offset },
-                        ..rotation
-                    };
-                    // check all points in the intersection
-                    let mut transformed_other_points = HashSet::new();
-
-                    let mut matches = 0;
-                    let mut conflict = false;
-                    for p in &other.points {
-                        let transformed_point = transform.apply(p);
-                        transformed_other_points.insert(transformed_point);
-                        // println!("examin point {:?} transformed {:?}", p, transformed_point);
-                        if in_bounds(&transformed_point, &min_bounds, &max_bounds) {
-                            // println!(
-                            //     "in bounds! contained {}",
-                            //     point_set.contains(&transformed_point)
-                            // );
-                            if point_set.contains(&transformed_point) {
-                                matches += 1;
-                            } else {
-                                conflict = true;
-                                break;
-                            }
-                        }
-                    }
-                    // println!("Transformed other points {:?}", transformed_other_points);
-                    if !conflict && matches >= min_overlap {
-                        for p in &self.points {
-                            // println!("v2 point {:?}", p);
-                            if in_bounds(p, &min_bounds, &max_bounds) {
-                                if !transformed_other_points.contains(p) {
-                                    // println!("conflict!");
-                                    conflict = true;
-                                    break;
-                                }
-                            }
-                        }
-                        if !conflict {
-                            return Some(transform);
-                        }
+                    let transform = Affine::new(rotation, offset);
+                    if let Some(alignment) = self.align(&point_set, other, &transform, config) {
+                        return Some(alignment);
                     }
                 }
             }
@@ -244,6 +345,196 @@ impl Scanner {
     }
 }
 
+fn squared_distance(a: &[i64; 3], b: &[i64; 3]) -> i64 {
+    a.zip(*b).map(|(x, y)| (x - y) * (x - y)).iter().sum()
+}
+
+/// How many of `shared` beacons, counted once per scanner reporting them, may
+/// be reported by only one scanner.
+fn max_mismatched(config: &AlignmentConfig, shared: usize) -> usize {
+    (config.max_mismatch_fraction * shared as f64).floor() as usize
+}
+
+/// The alignment under `transform`, if enough of the `shared` beacon reports
+/// pair up into `residuals`.
+fn accept_alignment(
+    config: &AlignmentConfig,
+    transform: &Affine,
+    shared: usize,
+    residuals: Vec<[i64; 3]>,
+) -> Option<Alignment> {
+    let matched = residuals.len();
+    let mismatched = shared - 2 * matched;
+    if (matched as i64) < config.min_overlap || mismatched > max_mismatched(config, shared) {
+        return None;
+    }
+    Some(Alignment {
+        transform: *transform,
+        matched,
+        mismatched,
+        residuals,
+    })
+}
+
+/// Counts the elements two sorted lists of squared distances have in common,
+/// with multiplicity. Distances differing by at most `slack` count as equal.
+fn common_count(a: &[i64], b: &[i64], slack: f64) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        let (x, y) = ((a[i] as f64).sqrt(), (b[j] as f64).sqrt());
+        if (x - y).abs() <= slack {
+            count += 1;
+            i += 1;
+            j += 1;
+        } else if x < y {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    count
+}
+
+/// Squared distances between the beacons of a scanner, which do not change
+/// under rotation or translation.
+#[derive(Debug)]
+struct Fingerprint {
+    /// For each beacon, the sorted distances to every other beacon.
+    beacons: Vec<Vec<i64>>,
+    /// The sorted distances between every pair of beacons.
+    pairs: Vec<i64>,
+}
+
+impl Fingerprint {
+    fn new(scanner: &Scanner) -> Fingerprint {
+        let beacons = scanner
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let mut distances = scanner
+                    .points
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, q)| squared_distance(p, q))
+                    .collect_vec();
+                distances.sort();
+                distances
+            })
+            .collect_vec();
+        let mut pairs = scanner
+            .points
+            .iter()
+            .tuple_combinations()
+            .map(|(p, q)| squared_distance(p, q))
+            .collect_vec();
+        pairs.sort();
+        Fingerprint { beacons, pairs }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct MatchStats {
+    /// Scanner pairs compared.
+    pairs: usize,
+    /// Scanner pairs rejected from their pairwise distances alone.
+    pruned: usize,
+    /// Beacon correspondences sharing enough distances to be tried.
+    correspondences: usize,
+    /// Candidate transforms checked against every beacon.
+    verified: usize,
+    /// Scanner pairs found to overlap.
+    matched: usize,
+}
+
+/// Finds the same transforms as `Scanner::matches`, but only verifies
+/// transforms that line up beacons with matching fingerprints.
+struct Matcher<'a> {
+    scanners: &'a [Scanner],
+    fingerprints: Vec<Fingerprint>,
+    config: AlignmentConfig,
+    stats: MatchStats,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(scanners: &'a [Scanner], config: AlignmentConfig) -> Matcher<'a> {
+        Matcher {
+            scanners,
+            fingerprints: scanners.iter().map(Fingerprint::new).collect(),
+            config,
+            stats: MatchStats::default(),
+        }
+    }
+
+    //  Returns the translation to apply to scanner j to match with scanner i.
+    fn matches(&mut self, i: usize, j: usize) -> Option<Alignment> {
+        self.stats.pairs += 1;
+        let min_overlap = self.config.min_overlap as usize;
+        // Each report of a beacon may be off by the tolerance on every axis.
+        let slack = 2.0 * 3f64.sqrt() * self.config.tolerance as f64;
+        let (fingerprint, other_fingerprint) = (&self.fingerprints[i], &self.fingerprints[j]);
+        // Every pair of shared beacons contributes a shared distance.
+        let min_shared_pairs = min_overlap * min_overlap.saturating_sub(1) / 2;
+        if common_count(&fingerprint.pairs, &other_fingerprint.pairs, slack) < min_shared_pairs {
+            self.stats.pruned += 1;
+            return None;
+        }
+        // A shared beacon is at the same distance from the other shared beacons
+        // in both scanners.
+        let mut candidates = iproduct!(
+            fingerprint.beacons.iter().enumerate(),
+            other_fingerprint.beacons.iter().enumerate()
+        )
+        .map(|((p, distances), (q, other_distances))| {
+            (common_count(distances, other_distances, slack), p, q)
+        })
+        .filter(|(shared, _, _)| shared + 1 >= min_overlap)
+        .collect_vec();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let (scanner, other) = (&self.scanners[i], &self.scanners[j]);
+        let point_set: HashSet<_> = scanner.points.iter().collect();
+        for (_, p, q) in candidates {
+            self.stats.correspondences += 1;
+            for rotation in Rotation::all() {
+                let offset = scanner.points[p]
+                    .zip(rotation.apply(&other.points[q]))
+                    .map(|(p, o)| p - o);
+                let transform = Affine::new(rotation, offset);
+                self.stats.verified += 1;
+                if let Some(alignment) = scanner.align(&point_set, other, &transform, &self.config)
+                {
+                    self.stats.matched += 1;
+                    return Some(alignment);
+                }
+            }
+        }
+        None
+    }
+
+    /// Compares every pair of scanners, returning for each scanner the
+    /// scanners overlapping it, with the transforms into its frame.
+    fn match_all(&mut self) -> HashMap<usize, Vec<(usize, Alignment)>> {
+        let mut edges = HashMap::new();
+        for i in 0..self.scanners.len() {
+            for j in i + 1..self.scanners.len() {
+                if let Some(alignment) = self.matches(i, j) {
+                    edges
+                        .entry(j)
+                        .or_insert_with(|| vec![])
+                        .push((i, alignment.inverse()));
+                    edges
+                        .entry(i)
+                        .or_insert_with(|| vec![])
+                        .push((j, alignment));
+                }
+            }
+        }
+        edges
+    }
+}
+
 fn load_scanners() -> Vec<Scanner> {
     let file = File::open("19.txt").unwrap();
     let lines: io::Lines<io::BufReader<File>> = io::BufReader::new(file).lines();
@@ -272,7 +563,10 @@ fn load_scanners() -> Vec<Scanner> {
 #[test]
 fn test_transformation() {
     let scanners = load_scanners();
-    let transform = Combination::new([false, false, false], [0, 1, 2], [5, 2, 0]);
+    let rotation = Rotation {
+        matrix: [[0, -1, 0], [0, 0, 1], [-1, 0, 0]],
+    };
+    let transform = Affine::new(rotation, [5, 2, 0]);
     assert!(scanners.iter().all(|s| s
         .points
         .iter()
@@ -293,9 +587,28 @@ fn test_transformation() {
 
 #[test]
 fn test_all_symmetries() {
-    let symmetries = all_symmetries().collect_vec();
-    println!("symmetries {:?}", symmetries);
-    println!("num symmetries {}", symmetries.len());
+    let symmetries: HashSet<Rotation> = Rotation::all().collect();
+    assert_eq!(symmetries.len(), 24);
+    assert!(symmetries.contains(&Rotation::id()));
+    for a in &symmetries {
+        assert!(symmetries.contains(&a.inverse()));
+        assert_eq!(a.compose(&a.inverse()), Rotation::id());
+        for b in &symmetries {
+            assert!(symmetries.contains(&a.compose(b)));
+        }
+    }
+}
+
+#[test]
+fn test_affine_compose() {
+    let rotations = Rotation::all().collect_vec();
+    let a = Affine::new(rotations[5], [1, -2, 3]);
+    let b = Affine::new(rotations[17], [-40, 7, 12]);
+    let point = [3, 14, -15];
+    assert_eq!(a.compose(&b).apply(&point), a.apply(&b.apply(&point)));
+    assert_eq!(a.compose(&b).inverse(), b.inverse().compose(&a.inverse()));
+    assert_eq!(a.compose(&a.inverse()), Affine::id());
+    assert_eq!(a.inverse().compose(&a), Affine::id());
 }
 
 #[test]
@@ -306,75 +619,352 @@ fn test_matches_2d() {
     let scanner2 = Scanner {
         points: vec![[-1, -1, 0], [-5, 0, 0], [-2, 1, 0]],
     };
-    println!("Matches {:?}", scanner1.matches(&scanner2, 1000, 3));
+    let config = AlignmentConfig {
+        min_overlap: 3,
+        ..AlignmentConfig::default()
+    };
+    println!("Matches {:?}", scanner1.matches(&scanner2, &config));
 }
 
-fn reconstruct(
-    scanners: &Vec<Scanner>,
-    edges: &HashMap<usize, Vec<(usize, Combination)>>,
-) -> (Vec<[i64; 3]>, HashSet<[i64; 3]>) {
-    let mut centers = Vec::new();
-    let mut points = HashSet::new();
-    let mut visited = HashSet::new();
-    let mut frontier: Vec<(usize, Vec<&Combination>)> = vec![(0usize, vec![])];
-    while let Some((i, transform)) = frontier.pop() {
-        println!("visiting {} ", i);
-        let mut offset = [0, 0, 0];
-        for &t in transform.iter().rev() {
-            offset = t.apply(&offset);
-            // println!("offset {:?}", offset);
-        }
-        centers.push(offset);
-        for point in &scanners[i].points {
-            let mut p = *point;
-            for &t in transform.iter().rev() {
-                p = t.apply(&p);
+/// An edge of the match graph left out of the spanning tree.
+#[derive(Debug, Clone, PartialEq)]
+struct CycleCheck {
+    /// The scanners the edge connects; its transform takes points of the
+    /// second into the frame of the first.
+    edge: (usize, usize),
+    /// The transform around the cycle the edge closes, from the second
+    /// scanner along the edge and back through the tree. The identity when the
+    /// matches agree.
+    residual: Affine,
+}
+
+impl CycleCheck {
+    /// Whether the cycle closes, up to `tolerance` along each axis.
+    fn closes(&self, tolerance: i64) -> bool {
+        self.residual.rotation == Rotation::id()
+            && self
+                .residual
+                .translation
+                .iter()
+                .all(|x| x.abs() <= tolerance)
+    }
+}
+
+/// The scanners laid out in one frame per connected group.
+#[derive(Debug)]
+struct ScannerMap {
+    /// The connected groups of scanners, each starting with the scanner whose
+    /// frame the group is reconstructed in.
+    components: Vec<Vec<usize>>,
+    /// For each scanner, the index of its group.
+    component_of: Vec<usize>,
+    /// For each scanner, the transform from its frame into its group's frame.
+    poses: Vec<Affine>,
+    /// The edges of the spanning forest, as (parent, child).
+    tree_edges: Vec<(usize, usize)>,
+    cycles: Vec<CycleCheck>,
+    /// How far a cycle may be from closing exactly, along each axis.
+    tolerance: i64,
+}
+
+impl ScannerMap {
+    fn is_consistent(&self) -> bool {
+        self.cycles.iter().all(|c| c.closes(self.tolerance))
+    }
+
+    fn inconsistent_cycles(&self) -> impl Iterator<Item = &CycleCheck> {
+        self.cycles.iter().filter(|c| !c.closes(self.tolerance))
+    }
+
+    /// The scanner positions of a group, in the group's frame.
+    fn centers(&self, component: usize) -> Vec<[i64; 3]> {
+        self.components[component]
+            .iter()
+            .map(|&i| self.poses[i].translation)
+            .collect()
+    }
+
+    fn beacons(&self, scanners: &[Scanner], component: usize) -> HashSet<[i64; 3]> {
+        self.components[component]
+            .iter()
+            .flat_map(|&i| {
+                scanners[i]
+                    .points
+                    .iter()
+                    .map(move |p| self.poses[i].apply(p))
+            })
+            .collect()
+    }
+
+    /// The beacons of a group, sorted by position, with the scanners that
+    /// reported each one. Reports from different scanners close enough for
+    /// `Scanner::align` to pair them count as one beacon, at their mean
+    /// position.
+    fn merged_beacons(
+        &self,
+        scanners: &[Scanner],
+        component: usize,
+    ) -> Vec<([i64; 3], Vec<usize>)> {
+        let window = 2 * self.tolerance;
+        let mut reports: Vec<(Vec<[i64; 3]>, Vec<usize>)> = vec![];
+        for &i in &self.components[component] {
+            for p in &scanners[i].points {
+                let p = self.poses[i].apply(p);
+                let merged = reports
+                    .iter_mut()
+                    .filter(|(positions, seen_by)| {
+                        !seen_by.contains(&i)
+                            && positions[0]
+                                .zip(p)
+                                .iter()
+                                .all(|(a, b)| (a - b).abs() <= window)
+                    })
+                    .min_by_key(|(positions, _)| squared_distance(&positions[0], &p));
+                match merged {
+                    Some((positions, seen_by)) => {
+                        positions.push(p);
+                        seen_by.push(i);
+                    }
+                    None => reports.push((vec![p], vec![i])),
+                }
             }
-            points.insert(p);
         }
-        // println!("points {:?}", points);
-        visited.insert(i);
-        if let Some(children) = edges.get(&i) {
-            for (child, child_transform) in children {
-                if !visited.contains(child) {
-                    let mut new_transform = transform.clone();
-                    new_transform.push(child_transform);
-                    frontier.push((*child, new_transform));
+        reports
+            .into_iter()
+            .map(|(positions, seen_by)| {
+                let count = positions.len() as f64;
+                let mean = [0, 1, 2].map(|axis| {
+                    let total: i64 = positions.iter().map(|p| p[axis]).sum();
+                    (total as f64 / count).round() as i64
+                });
+                (mean, seen_by)
+            })
+            .sorted()
+            .collect()
+    }
+}
+
+/// Lays out every scanner along a breadth first spanning forest of the match
+/// graph, then checks every other match against the layout.
+fn reconstruct(
+    num_scanners: usize,
+    edges: &HashMap<usize, Vec<(usize, Alignment)>>,
+    config: &AlignmentConfig,
+) -> ScannerMap {
+    let mut components = vec![];
+    let mut component_of = vec![usize::MAX; num_scanners];
+    let mut poses = vec![Affine::id(); num_scanners];
+    let mut tree_edges = vec![];
+    for root in 0..num_scanners {
+        if component_of[root] != usize::MAX {
+            continue;
+        }
+        let component = components.len();
+        let mut members = vec![root];
+        component_of[root] = component;
+        let mut frontier = VecDeque::from([root]);
+        while let Some(i) = frontier.pop_front() {
+            for (child, alignment) in edges.get(&i).into_iter().flatten() {
+                if component_of[*child] == usize::MAX {
+                    component_of[*child] = component;
+                    poses[*child] = poses[i].compose(&alignment.transform);
+                    tree_edges.push((i, *child));
+                    members.push(*child);
+                    frontier.push_back(*child);
                 }
             }
         }
+        components.push(members);
     }
 
-    return (centers, points);
+    let tree_set: HashSet<_> = tree_edges.iter().cloned().collect();
+    let cycles = edges
+        .iter()
+        .flat_map(|(&i, children)| children.iter().map(move |(j, a)| (i, *j, a)))
+        .filter(|&(i, j, _)| i < j && !tree_set.contains(&(i, j)) && !tree_set.contains(&(j, i)))
+        .map(|(i, j, alignment)| CycleCheck {
+            edge: (i, j),
+            residual: poses[j]
+                .inverse()
+                .compose(&poses[i])
+                .compose(&alignment.transform),
+        })
+        .sorted_by_key(|c| c.edge)
+        .collect();
+
+    ScannerMap {
+        components,
+        component_of,
+        poses,
+        tree_edges,
+        cycles,
+        tolerance: config.tolerance,
+    }
 }
-#[test]
-fn part12() {
-    let scanners = load_scanners();
-    let mut edges = HashMap::new();
-    for (i, scanner1) in scanners.iter().enumerate() {
-        for (j, scanner2) in scanners.iter().enumerate() {
-            if i < j {
-                println!("Comparing {} and {}", i, j);
-                if let Some(transform) = scanner1.matches(&scanner2, 1000, 12) {
-                    println!("found match {:?}", transform);
 
-                    edges
-                        .entry(i)
-                        .or_insert_with(|| vec![])
-                        .push((j, transform));
-                    edges
-                        .entry(j)
-                        .or_insert_with(|| vec![])
-                        .push((i, transform.inverse()));
-                }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    /// One row per scanner or beacon. Rotations are written as their nine
+    /// entries in row-major order.
+    Csv,
+    Json,
+    /// An ASCII PLY point cloud. Scanners are red, with their normal along
+    /// their own x axis and an empty `seen_by` list; beacons are white.
+    Ply,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Ply => "ply",
+        }
+    }
+}
+
+/// Writes the beacons and scanner poses of one group of `map`, tagging each
+/// beacon with the scanners that reported it.
+fn export_map<W: Write>(
+    out: &mut W,
+    format: ExportFormat,
+    map: &ScannerMap,
+    scanners: &[Scanner],
+    component: usize,
+) -> io::Result<()> {
+    let members = &map.components[component];
+    let beacons = map.merged_beacons(scanners, component);
+    let join = |values: &[i64], separator: &str| values.iter().join(separator);
+    match format {
+        ExportFormat::Csv => {
+            writeln!(out, "kind,id,x,y,z,seen_by,rotation")?;
+            for &i in members {
+                let pose = &map.poses[i];
+                writeln!(
+                    out,
+                    "scanner,{},{},,{}",
+                    i,
+                    join(&pose.translation, ","),
+                    join(pose.rotation.matrix.concat().as_slice(), " ")
+                )?;
+            }
+            for (id, (position, seen_by)) in beacons.iter().enumerate() {
+                writeln!(
+                    out,
+                    "beacon,{},{},{},",
+                    id,
+                    join(position, ","),
+                    seen_by.iter().join(" ")
+                )?;
+            }
+        }
+        ExportFormat::Json => {
+            writeln!(out, "{{")?;
+            writeln!(out, "  \"scanners\": [")?;
+            for (k, &i) in members.iter().enumerate() {
+                let pose = &map.poses[i];
+                writeln!(
+                    out,
+                    "    {{\"id\": {}, \"position\": [{}], \"rotation\": [{}]}}{}",
+                    i,
+                    join(&pose.translation, ", "),
+                    pose.rotation
+                        .matrix
+                        .iter()
+                        .map(|row| format!("[{}]", join(row, ", ")))
+                        .join(", "),
+                    if k + 1 < members.len() { "," } else { "" }
+                )?;
+            }
+            writeln!(out, "  ],")?;
+            writeln!(out, "  \"beacons\": [")?;
+            for (k, (position, seen_by)) in beacons.iter().enumerate() {
+                writeln!(
+                    out,
+                    "    {{\"position\": [{}], \"seen_by\": [{}]}}{}",
+                    join(position, ", "),
+                    seen_by.iter().join(", "),
+                    if k + 1 < beacons.len() { "," } else { "" }
+                )?;
+            }
+            writeln!(out, "  ]")?;
+            writeln!(out, "}}")?;
+        }
+        ExportFormat::Ply => {
+            writeln!(out, "ply")?;
+            writeln!(out, "format ascii 1.0")?;
+            writeln!(
+                out,
+                "comment {} scanners, {} beacons",
+                members.len(),
+                beacons.len()
+            )?;
+            writeln!(out, "element vertex {}", members.len() + beacons.len())?;
+            for property in [
+                "float x", "float y", "float z", "float nx", "float ny", "float nz",
+            ] {
+                writeln!(out, "property {}", property)?;
+            }
+            for property in [
+                "uchar red",
+                "uchar green",
+                "uchar blue",
+                "list uchar int seen_by",
+            ] {
+                writeln!(out, "property {}", property)?;
+            }
+            writeln!(out, "end_header")?;
+            for &i in members {
+                let pose = &map.poses[i];
+                let facing = pose.rotation.apply(&[1, 0, 0]);
+                writeln!(
+                    out,
+                    "{} {} 255 0 0 0",
+                    join(&pose.translation, " "),
+                    join(&facing, " ")
+                )?;
+            }
+            for (position, seen_by) in &beacons {
+                writeln!(
+                    out,
+                    "{} 0 0 0 255 255 255 {} {}",
+                    join(position, " "),
+                    seen_by.len(),
+                    seen_by.iter().join(" ")
+                )?;
             }
         }
     }
+    Ok(())
+}
+
+#[test]
+fn part12() {
+    let scanners = load_scanners();
+    let mut matcher = Matcher::new(&scanners, AlignmentConfig::default());
+    let edges = matcher.match_all();
     println!("matches {:?}", edges);
-    let (centers, reconstructed) = reconstruct(&scanners, &edges);
+    println!("match stats {:?}", matcher.stats);
+    let map = reconstruct(scanners.len(), &edges, &matcher.config);
+    for (i, pose) in map.poses.iter().enumerate() {
+        println!("scanner {} pose {:?}", i, pose);
+    }
+    for cycle in map.inconsistent_cycles() {
+        println!("inconsistent cycle {:?}", cycle);
+    }
+    if map.components.len() > 1 {
+        println!("disconnected components {:?}", map.components);
+    }
+    for format in [ExportFormat::Csv, ExportFormat::Json, ExportFormat::Ply] {
+        let mut out = vec![];
+        export_map(&mut out, format, &map, &scanners, 0).unwrap();
+        println!("{} export {} bytes", format.extension(), out.len());
+    }
+    let reconstructed = map.beacons(&scanners, 0);
     println!("reconstructed {:?}", reconstructed);
     println!("size {}", reconstructed.len());
 
+    let centers = map.centers(0);
     let max_distance: i64 = centers
         .iter()
         .filter_map(|c1| {
@@ -387,3 +977,312 @@ fn part12() {
         .unwrap();
     println!("max distance {}", max_distance);
 }
+
+/// Scanners at the given positions and orientations, each seeing the beacons
+/// within 1000 of it on every axis.
+fn simulate_scanners(beacons: &[[i64; 3]], poses: &[Affine]) -> Vec<Scanner> {
+    poses
+        .iter()
+        .map(|pose| {
+            let to_scanner = pose.inverse();
+            Scanner {
+                points: beacons
+                    .iter()
+                    .map(|b| to_scanner.apply(b))
+                    .filter(|p| in_bounds(p, &[-1000; 3], &[1000; 3]))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Random beacons and the scanners that see them.
+#[cfg(test)]
+struct RandomScanners {
+    /// The generator the beacons came from, for any noise a test adds.
+    rng: crate::Lcg,
+    beacons: Vec<[i64; 3]>,
+    poses: Vec<Affine>,
+    scanners: Vec<Scanner>,
+}
+
+/// `count` beacons with each coordinate between -1000 and the matching entry
+/// of `bounds`, seen by scanners at `poses`, each given as an index into
+/// `Rotation::all()` and a position.
+#[cfg(test)]
+fn random_scanners(
+    seed: u64,
+    count: usize,
+    bounds: [i64; 3],
+    poses: &[(usize, [i64; 3])],
+) -> RandomScanners {
+    let mut rng = crate::Lcg::new(seed);
+    let beacons = (0..count)
+        .map(|_| bounds.map(|bound| rng.range(-1000, bound)))
+        .collect_vec();
+    let rotations = Rotation::all().collect_vec();
+    let poses = poses
+        .iter()
+        .map(|&(rotation, position)| Affine::new(rotations[rotation], position))
+        .collect_vec();
+    let scanners = simulate_scanners(&beacons, &poses);
+    RandomScanners {
+        rng,
+        beacons,
+        poses,
+        scanners,
+    }
+}
+
+#[test]
+fn test_fingerprint_matches() {
+    let RandomScanners {
+        poses, scanners, ..
+    } = random_scanners(
+        19,
+        150,
+        [1600, 1000, 3500],
+        &[(0, [0, 0, 0]), (7, [600, 0, 0]), (19, [0, 0, 2500])],
+    );
+
+    let config = AlignmentConfig::default();
+    let mut matcher = Matcher::new(&scanners, config);
+    for (i, j) in (0..scanners.len()).tuple_combinations() {
+        let expected = scanners[i].matches(&scanners[j], &config);
+        assert_eq!(matcher.matches(i, j), expected);
+    }
+    assert_eq!(
+        matcher.matches(0, 1).map(|alignment| alignment.transform),
+        Some(poses[0].inverse().compose(&poses[1]))
+    );
+    println!("match stats {:?}", matcher.stats);
+    assert_eq!(matcher.stats.pairs, 4);
+    assert_eq!(matcher.stats.matched, 2);
+    assert!(matcher.stats.pruned >= 1);
+}
+
+#[test]
+fn test_tolerant_alignment() {
+    let RandomScanners {
+        mut rng,
+        poses,
+        mut scanners,
+        ..
+    } = random_scanners(
+        33,
+        120,
+        [1600, 1000, 1000],
+        &[(0, [0, 0, 0]), (11, [600, 0, 0])],
+    );
+    // The second scanner misses some beacons, reports some that are not
+    // there, and is off by one on every reading.
+    let noisy = &mut scanners[1].points;
+    noisy.truncate(noisy.len() - 3);
+    for _ in 0..2 {
+        noisy.push([
+            rng.range(-1000, 1000),
+            rng.range(-1000, 1000),
+            rng.range(-1000, 1000),
+        ]);
+    }
+    for point in noisy.iter_mut() {
+        *point = point.map(|x| x + rng.range(-1, 1));
+    }
+
+    let strict = AlignmentConfig::default();
+    assert_eq!(Matcher::new(&scanners, strict).matches(0, 1), None);
+
+    let tolerant = AlignmentConfig {
+        max_mismatch_fraction: 0.2,
+        tolerance: 1,
+        ..strict
+    };
+    let mut matcher = Matcher::new(&scanners, tolerant);
+    let alignment = matcher.matches(0, 1).unwrap();
+    println!("alignment {:?}", alignment);
+    assert_eq!(alignment.transform, poses[1]);
+    assert!(alignment.mismatched > 0);
+    assert!(alignment.matched >= 12);
+    assert!(alignment
+        .residuals
+        .iter()
+        .all(|r| r.iter().all(|x| x.abs() <= tolerant.tolerance)));
+    println!("rms residual {}", alignment.rms_residual());
+}
+
+#[test]
+fn test_reconstruct_components() {
+    // Three scanners that all overlap each other, and one far away with a
+    // cluster of its own.
+    let RandomScanners {
+        mut rng,
+        mut beacons,
+        poses,
+        ..
+    } = random_scanners(
+        34,
+        150,
+        [1500, 1400, 1000],
+        &[
+            (3, [0, 0, 0]),
+            (9, [500, 0, 0]),
+            (22, [250, 400, 0]),
+            (14, [0, 0, 5000]),
+        ],
+    );
+    beacons.extend((0..20).map(|_| {
+        [
+            rng.range(-500, 500),
+            rng.range(-500, 500),
+            rng.range(4500, 5500),
+        ]
+    }));
+    let scanners = simulate_scanners(&beacons, &poses);
+    let config = AlignmentConfig::default();
+    let mut edges = Matcher::new(&scanners, config).match_all();
+
+    let map = reconstruct(scanners.len(), &edges, &config);
+    assert_eq!(map.components, [vec![0, 1, 2], vec![3]]);
+    assert_eq!(map.component_of, [0, 0, 0, 1]);
+    assert_eq!(map.tree_edges.len(), 2);
+    assert_eq!(map.cycles.len(), 1);
+    assert!(map.is_consistent());
+    for i in 0..3 {
+        assert_eq!(map.poses[i], poses[0].inverse().compose(&poses[i]));
+    }
+    assert_eq!(map.poses[3], Affine::id());
+    let expected_beacons: HashSet<_> = beacons
+        .iter()
+        .filter(|b| {
+            (0..3).any(|i| in_bounds(&poses[i].inverse().apply(b), &[-1000; 3], &[1000; 3]))
+        })
+        .map(|b| poses[0].inverse().apply(b))
+        .collect();
+    assert_eq!(map.beacons(&scanners, 0), expected_beacons);
+
+    // Corrupt the match between 1 and 2; the cycle no longer closes.
+    for (i, j) in [(1, 2), (2, 1)] {
+        let alignment = &mut edges
+            .get_mut(&i)
+            .unwrap()
+            .iter_mut()
+            .find(|e| e.0 == j)
+            .unwrap()
+            .1;
+        alignment.transform.translation[0] += 1;
+    }
+    let map = reconstruct(scanners.len(), &edges, &config);
+    assert!(!map.is_consistent());
+    assert_eq!(map.inconsistent_cycles().count(), 1);
+}
+
+#[test]
+fn test_reconstruct_noisy() {
+    let RandomScanners {
+        mut rng,
+        poses,
+        mut scanners,
+        ..
+    } = random_scanners(
+        35,
+        200,
+        [1500, 1400, 1000],
+        &[(5, [0, 0, 0]), (12, [500, 0, 0]), (20, [250, 400, 0])],
+    );
+    for point in scanners.iter_mut().flat_map(|s| s.points.iter_mut()) {
+        *point = point.map(|x| x + rng.range(-1, 1));
+    }
+    let config = AlignmentConfig {
+        tolerance: 1,
+        ..AlignmentConfig::default()
+    };
+    let edges = Matcher::new(&scanners, config).match_all();
+    let map = reconstruct(scanners.len(), &edges, &config);
+    assert_eq!(map.components, [vec![0, 1, 2]]);
+    assert_eq!(map.cycles.len(), 1);
+    assert!(map.is_consistent());
+    for i in 0..3 {
+        assert_eq!(map.poses[i], poses[0].inverse().compose(&poses[i]));
+    }
+
+    // The cycle tolerates a drift of up to the tolerance, but no more.
+    let (i, j) = map.cycles[0].edge;
+    for (drift, consistent) in [(config.tolerance, true), (config.tolerance + 1, false)] {
+        let mut drifted = edges.clone();
+        drifted
+            .get_mut(&i)
+            .unwrap()
+            .iter_mut()
+            .find(|e| e.0 == j)
+            .unwrap()
+            .1
+            .transform
+            .translation[0] += drift;
+        let map = reconstruct(scanners.len(), &drifted, &config);
+        assert_eq!(map.is_consistent(), consistent);
+    }
+}
+
+#[test]
+fn test_export_map() {
+    let rotations = Rotation::all().collect_vec();
+    let poses = [Affine::id(), Affine::new(rotations[4], [100, 0, 0])];
+    let beacons = [[1, 2, 3], [50, -5, 7], [1050, 0, 0]];
+    let scanners = simulate_scanners(&beacons, &poses);
+    let mut edges = HashMap::new();
+    let alignment = Alignment {
+        transform: poses[1],
+        matched: 2,
+        mismatched: 0,
+        residuals: vec![[0; 3]; 2],
+    };
+    edges.insert(1, vec![(0, alignment.inverse())]);
+    edges.insert(0, vec![(1, alignment)]);
+    let map = reconstruct(scanners.len(), &edges, &AlignmentConfig::default());
+
+    let export = |format| {
+        let mut out = vec![];
+        export_map(&mut out, format, &map, &scanners, 0).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let rotation = rotations[4].matrix.concat().iter().join(" ");
+    assert_eq!(
+        export(ExportFormat::Csv),
+        format!(
+            "kind,id,x,y,z,seen_by,rotation\n\
+             scanner,0,0,0,0,,1 0 0 0 1 0 0 0 1\n\
+             scanner,1,100,0,0,,{}\n\
+             beacon,0,1,2,3,0 1,\n\
+             beacon,1,50,-5,7,0 1,\n\
+             beacon,2,1050,0,0,1,\n",
+            rotation
+        )
+    );
+    let json = export(ExportFormat::Json);
+    assert!(json.contains(
+        "{\"id\": 0, \"position\": [0, 0, 0], \"rotation\": [[1, 0, 0], [0, 1, 0], [0, 0, 1]]},"
+    ));
+    assert!(json.contains("{\"position\": [1050, 0, 0], \"seen_by\": [1]}\n  ]"));
+    let ply = export(ExportFormat::Ply);
+    assert!(ply.starts_with("ply\nformat ascii 1.0\n"));
+    assert!(ply.contains("element vertex 5\n"));
+    assert!(ply.contains("property list uchar int seen_by\n"));
+    assert!(ply.contains("\n1 2 3 0 0 0 255 255 255 2 0 1\n"));
+    assert!(ply.ends_with("1050 0 0 0 0 0 255 255 255 1 1\n"));
+    assert_eq!(ply.lines().skip_while(|l| *l != "end_header").count(), 6);
+
+    // Reports off by one still count as the same beacon when tolerated.
+    let mut noisy = simulate_scanners(&beacons, &poses);
+    for point in noisy[1].points.iter_mut() {
+        point[0] += 1;
+    }
+    let tolerant = AlignmentConfig {
+        tolerance: 1,
+        ..AlignmentConfig::default()
+    };
+    let map = reconstruct(noisy.len(), &edges, &tolerant);
+    let merged = map.merged_beacons(&noisy, 0);
+    assert_eq!(merged.len(), 3);
+    assert_eq!(merged[2].1, [1]);
+    assert!(merged[..2].iter().all(|(_, seen_by)| seen_by == &[0, 1]));
+}
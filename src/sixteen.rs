@@ -1,5 +1,6 @@
 use bitreader::BitReader;
 use hex::FromHex;
+use num::{BigUint, One, ToPrimitive, Zero};
 use std::fs::File;
 use std::io::{self, BufRead};
 
@@ -10,12 +11,26 @@ fn load_array() -> Vec<u8> {
     let hexstring = lines.next().unwrap().unwrap();
     return Vec::from_hex(hexstring).unwrap();
 }
+
+fn parse_hex(hexstring: &str) -> Packet {
+    let array: Vec<u8> = Vec::from_hex(hexstring).unwrap();
+    let mut reader = BitReader::new(array.as_slice());
+    Packet::parse(&mut reader)
+}
 #[derive(Debug)]
 struct Packet {
     version: u8,
     kind: u8,
     payload: Payload,
 }
+/// Returned by `Packet::eval_checked` when the value of a packet does not fit
+/// in a `u64`. `value` is the exact result.
+#[derive(Debug, PartialEq, Eq)]
+struct Overflow {
+    kind: u8,
+    value: BigUint,
+}
+
 #[derive(Debug)]
 enum OperatorLength {
     Bits(u64),
@@ -24,7 +39,7 @@ enum OperatorLength {
 #[derive(Debug)]
 enum Payload {
     Literal {
-        value: u64,
+        value: BigUint,
     },
     Operator {
         length: OperatorLength,
@@ -48,12 +63,11 @@ impl Packet {
         let version = reader.read_u8(3).unwrap();
         let kind = reader.read_u8(3).unwrap();
         if kind == 4 {
-            let mut literal_value: u64 = 0;
+            let mut literal_value = BigUint::zero();
             while {
                 let more_packets = reader.read_bool().unwrap();
                 let bytes = reader.read_u64(4).unwrap();
-                literal_value <<= 4;
-                literal_value |= bytes;
+                literal_value = (literal_value << 4) | BigUint::from(bytes);
                 more_packets
             } {}
             Packet {
@@ -84,21 +98,61 @@ impl Packet {
         }
     }
 
-    fn eval(&self) -> u64 {
+    fn eval(&self) -> BigUint {
         match &self.payload {
-            Payload::Literal { value } => *value,
+            Payload::Literal { value } => value.clone(),
             Payload::Operator { children, .. } => match self.kind {
-                0 => children.iter().map(Packet::eval).sum(),
-                1 => children.iter().map(Packet::eval).product(),
+                0 => children
+                    .iter()
+                    .map(Packet::eval)
+                    .fold(BigUint::zero(), |a, b| a + b),
+                1 => children
+                    .iter()
+                    .map(Packet::eval)
+                    .fold(BigUint::one(), |a, b| a * b),
                 2 => children.iter().map(Packet::eval).min().unwrap(),
                 3 => children.iter().map(Packet::eval).max().unwrap(),
-                5 => if children[0].eval() > children[1].eval() {1} else {0},
-                6 => if children[0].eval() < children[1].eval() {1} else {0},
-                7 => if children[0].eval() == children[1].eval() {1} else {0},
+                5 => BigUint::from((children[0].eval() > children[1].eval()) as u64),
+                6 => BigUint::from((children[0].eval() < children[1].eval()) as u64),
+                7 => BigUint::from((children[0].eval() == children[1].eval()) as u64),
                 _ => panic!("Unrecognized operator!"),
             },
         }
     }
+
+    /// Like `eval`, but in `u64` arithmetic, reporting the first packet whose
+    /// value overflows instead of wrapping.
+    fn eval_checked(&self) -> Result<u64, Overflow> {
+        let overflow = || Overflow {
+            kind: self.kind,
+            value: self.eval(),
+        };
+        match &self.payload {
+            Payload::Literal { value } => value.to_u64().ok_or_else(overflow),
+            Payload::Operator { children, .. } => {
+                let values: Vec<u64> = children
+                    .iter()
+                    .map(Packet::eval_checked)
+                    .collect::<Result<_, _>>()?;
+                match self.kind {
+                    0 => values
+                        .iter()
+                        .try_fold(0u64, |a, b| a.checked_add(*b))
+                        .ok_or_else(overflow),
+                    1 => values
+                        .iter()
+                        .try_fold(1u64, |a, b| a.checked_mul(*b))
+                        .ok_or_else(overflow),
+                    2 => Ok(*values.iter().min().unwrap()),
+                    3 => Ok(*values.iter().max().unwrap()),
+                    5 => Ok((values[0] > values[1]) as u64),
+                    6 => Ok((values[0] < values[1]) as u64),
+                    7 => Ok((values[0] == values[1]) as u64),
+                    _ => panic!("Unrecognized operator!"),
+                }
+            }
+        }
+    }
 }
 
 fn version_sum(packet: &Packet) -> u64 {
@@ -127,3 +181,59 @@ fn part2() {
     println!("packet {:#?}", packet);
     println!("value {}", packet.eval());
 }
+
+#[test]
+fn test_eval_examples() {
+    let examples = [
+        ("C200B40A82", 3),
+        ("04005AC33890", 54),
+        ("880086C3E88112", 7),
+        ("CE00C43D881120", 9),
+        ("D8005AC2A8F0", 1),
+        ("F600BC2D8F", 0),
+        ("9C005AC2F8F0", 0),
+        ("9C0141080250320F1802104A08", 1),
+    ];
+    for (hexstring, expected) in examples {
+        let packet = parse_hex(hexstring);
+        assert_eq!(packet.eval(), BigUint::from(expected as u64));
+        assert_eq!(packet.eval_checked(), Ok(expected));
+    }
+}
+
+#[test]
+fn test_eval_overflow() {
+    // A single literal of 19 nibbles.
+    let literal = parse_hex("12329D2B6BE33ADF3BEFC23218");
+    let expected = BigUint::parse_bytes(b"123456789ABCDEF0123", 16).unwrap();
+    assert_eq!(literal.eval(), expected);
+    assert_eq!(
+        literal.eval_checked(),
+        Err(Overflow {
+            kind: 4,
+            value: expected
+        })
+    );
+
+    // 2^40 * 2^40
+    let product = parse_hex("0600848C2108421084002461084210842000");
+    assert_eq!(product.eval(), BigUint::one() << 80);
+    assert_eq!(
+        product.eval_checked(),
+        Err(Overflow {
+            kind: 1,
+            value: BigUint::one() << 80
+        })
+    );
+
+    // (2^64 - 1) + 1
+    let sum = parse_hex("020084FFFFFFFFFFFFFFFFFFEF1020");
+    assert_eq!(sum.eval(), BigUint::one() << 64);
+    assert_eq!(
+        sum.eval_checked(),
+        Err(Overflow {
+            kind: 0,
+            value: BigUint::one() << 64
+        })
+    );
+}
@@ -1,8 +1,11 @@
+use crate::automaton::{Automaton, Boundary, Neighborhood, Update};
 use array2d;
 use array2d::Array2D;
-use itertools::Itertools;
+use itertools::{iproduct, Itertools};
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 fn load_grid() -> (Vec<bool>, array2d::Array2D<bool>) {
     let file = File::open("20.txt").unwrap();
@@ -55,28 +58,670 @@ fn step(key: &Vec<bool>, grid: &Array2D<bool>, background: bool) -> (Array2D<boo
         },
     );
 }
+/// The enhancement as a synchronous automaton on an unlit background,
+/// equivalent to `step`.
+fn enhancement_automaton(key: Vec<bool>, grid: &Array2D<bool>) -> Automaton<bool> {
+    Automaton::new(
+        grid.clone(),
+        Boundary::Background(false),
+        Update::Synchronous {
+            neighborhood: Neighborhood::square(1),
+            rule: Rc::new(move |_, neighbors| {
+                key[neighbors
+                    .iter()
+                    .fold(0, |index, n| index * 2 + n.unwrap() as usize)]
+            }),
+        },
+    )
+}
+
+/// A bit-packed image on a finite canvas, surrounded by an infinite
+/// background of a single color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BitImage {
+    rows: usize,
+    columns: usize,
+    /// Words per row; bits past `columns` are always zero.
+    words: usize,
+    bits: Vec<u64>,
+    background: bool,
+}
+
+impl BitImage {
+    fn new(rows: usize, columns: usize, background: bool) -> BitImage {
+        let words = (columns + 63) / 64;
+        BitImage {
+            rows,
+            columns,
+            words,
+            bits: vec![0; rows * words],
+            background,
+        }
+    }
+
+    /// Copies `grid` into the middle of a canvas with `margin` background
+    /// pixels on every side.
+    fn from_grid(grid: &Array2D<bool>, margin: usize, background: bool) -> BitImage {
+        let mut image = BitImage::new(
+            grid.num_rows() + 2 * margin,
+            grid.num_columns() + 2 * margin,
+            background,
+        );
+        for row in 0..image.rows {
+            for col in 0..image.columns {
+                let value = match (row.checked_sub(margin), col.checked_sub(margin)) {
+                    (Some(r), Some(c)) => *grid.get(r, c).unwrap_or(&background),
+                    _ => background,
+                };
+                image.set(row, col, value);
+            }
+        }
+        image
+    }
+
+    /// The pixel at (`row`, `col`), which may lie outside the canvas.
+    fn get(&self, row: i64, col: i64) -> bool {
+        if row < 0 || col < 0 || row as usize >= self.rows || col as usize >= self.columns {
+            return self.background;
+        }
+        let (row, col) = (row as usize, col as usize);
+        self.bits[row * self.words + col / 64] >> (col % 64) & 1 == 1
+    }
+
+    /// Word `word` of `row`, which may lie outside the canvas. Pixels past
+    /// the last column read as background.
+    fn word(&self, row: i64, word: i64) -> u64 {
+        let fill = if self.background { !0 } else { 0 };
+        if row < 0 || row as usize >= self.rows || word < 0 || word as usize >= self.words {
+            return fill;
+        }
+        let bits = self.bits[row as usize * self.words + word as usize];
+        match self.columns - 64 * word as usize {
+            used if used < 64 => bits | fill << used,
+            _ => bits,
+        }
+    }
+
+    /// The pixels of `row` from column `64 * word - radius` up to column
+    /// `64 * word + 63 + radius`, lowest bit first.
+    fn window(&self, row: i64, word: usize, radius: usize) -> u128 {
+        let word = word as i64;
+        let (previous, current, next) = (
+            self.word(row, word - 1) as u128,
+            self.word(row, word) as u128,
+            self.word(row, word + 1) as u128,
+        );
+        (previous << radius >> 64) | current << radius | next << (64 + radius)
+    }
+
+    fn set(&mut self, row: usize, col: usize, value: bool) {
+        let word = &mut self.bits[row * self.words + col / 64];
+        if value {
+            *word |= 1 << (col % 64);
+        } else {
+            *word &= !(1 << (col % 64));
+        }
+    }
+
+    /// The number of lit pixels, or `None` if infinitely many are lit.
+    fn lit(&self) -> Option<usize> {
+        if self.background {
+            None
+        } else {
+            Some(self.bits.iter().map(|w| w.count_ones() as usize).sum())
+        }
+    }
+
+    fn to_grid(&self) -> Array2D<bool> {
+        Array2D::from_row_major(
+            &iproduct!(0..self.rows as i64, 0..self.columns as i64)
+                .map(|(r, c)| self.get(r, c))
+                .collect_vec(),
+            self.rows,
+            self.columns,
+        )
+    }
+}
+
+/// An image enhancement rule: each output pixel is looked up in `key` by the
+/// square of pixels within `radius` of it, read row by row as a binary
+/// number with the top left pixel as the most significant bit.
+#[derive(Debug, Clone)]
+struct Enhancer {
+    key: Vec<bool>,
+    radius: usize,
+}
+
+impl Enhancer {
+    /// The key needs an entry for every neighborhood, 2^((2r+1)^2) of them,
+    /// so radius 2 with 2^25 entries is the practical limit: radius 3 would
+    /// pass the checks below but need 2^49.
+    fn new(key: Vec<bool>, radius: usize) -> Enhancer {
+        let width = 2 * radius + 1;
+        assert!(width * width < 64, "neighborhood too large");
+        assert_eq!(key.len(), 1 << (width * width), "key does not match radius");
+        Enhancer { key, radius }
+    }
+
+    fn next_background(&self, background: bool) -> bool {
+        if background {
+            self.key[self.key.len() - 1]
+        } else {
+            self.key[0]
+        }
+    }
+
+    /// Enhances `from` into `to`, which must have the same canvas size.
+    ///
+    /// The caller must leave enough margin that pixels just outside the
+    /// canvas would have stayed background.
+    fn step_into(&self, from: &BitImage, to: &mut BitImage) {
+        assert_eq!((from.rows, from.columns), (to.rows, to.columns));
+        let r = self.radius;
+        let width = 2 * r + 1;
+        let mask = (1u128 << width) - 1;
+        to.background = self.next_background(from.background);
+        let mut windows = [0u128; 8];
+        for row in 0..from.rows {
+            for word in 0..from.words {
+                // Reversed, so the neighborhood of column `64 * word + j` is
+                // `width` bits down from the top bit, leftmost pixel highest
+                // as in the key's indices.
+                for (i, window) in windows[..width].iter_mut().enumerate() {
+                    *window = from
+                        .window(row as i64 - r as i64 + i as i64, word, r)
+                        .reverse_bits();
+                }
+                let mut bits = 0u64;
+                for j in 0..std::cmp::min(64, from.columns - 64 * word) {
+                    let shift = 127 - j - 2 * r;
+                    let index = windows[..width]
+                        .iter()
+                        .fold(0, |index, window| index << width | (window >> shift) & mask);
+                    bits |= (self.key[index as usize] as u64) << j;
+                }
+                to.bits[row * to.words + word] = bits;
+            }
+        }
+    }
+
+    /// Runs `generations` enhancements of `grid` on an infinite background
+    /// that starts unlit, returning the final image and the number of lit
+    /// pixels in every generation, starting with the input.
+    fn run(&self, grid: &Array2D<bool>, generations: usize) -> (BitImage, Vec<Option<usize>>) {
+        let mut lit = vec![];
+        let image = self.run_with(grid, generations, |_, image| lit.push(image.lit()));
+        (image, lit)
+    }
+
+    /// Like `run`, but calls `visit` with every generation, starting with
+    /// the input. All generations share the same canvas.
+    fn run_with<F: FnMut(usize, &BitImage)>(
+        &self,
+        grid: &Array2D<bool>,
+        generations: usize,
+        mut visit: F,
+    ) -> BitImage {
+        let mut image = BitImage::from_grid(grid, self.radius * generations, false);
+        let mut buffer = image.clone();
+        visit(0, &image);
+        for generation in 1..=generations {
+            self.step_into(&image, &mut buffer);
+            std::mem::swap(&mut image, &mut buffer);
+            visit(generation, &image);
+        }
+        image
+    }
+
+    /// Writes every generation as a numbered frame into `dir`, returning
+    /// the paths written.
+    fn animate(
+        &self,
+        grid: &Array2D<bool>,
+        generations: usize,
+        dir: &Path,
+        format: ImageFormat,
+    ) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        let mut result = Ok(());
+        self.run_with(grid, generations, |generation, image| {
+            if result.is_err() {
+                return;
+            }
+            let path = dir.join(format!("frame_{:04}.{}", generation, format.extension()));
+            result = File::create(&path).and_then(|file| {
+                let mut out = io::BufWriter::new(file);
+                write_image(&mut out, format, &image.to_grid())?;
+                out.flush()
+            });
+            paths.push(path);
+        });
+        result.map(|_| paths)
+    }
+}
+
+/// Renders `grid` in the puzzle's `#`/`.` text format.
+fn format_grid(grid: &Array2D<bool>) -> String {
+    grid.rows_iter()
+        .map(|row| {
+            row.map(|lit| if *lit { '#' } else { '.' })
+                .collect::<String>()
+                + "\n"
+        })
+        .collect()
+}
+
+/// Parses an image in the puzzle's `#`/`.` text format.
+fn parse_grid(text: &str) -> Array2D<bool> {
+    Array2D::from_rows(
+        &text
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.chars().map(|c| c == '#').collect_vec())
+            .collect_vec(),
+    )
+}
+
+/// Netpbm image formats. Lit pixels are drawn black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageFormat {
+    /// Plain bitmap, `P1`.
+    Pbm,
+    /// Plain 8-bit graymap, `P2`.
+    Pgm,
+}
+
+impl ImageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Pgm => "pgm",
+        }
+    }
+}
+
+fn write_image<W: Write>(out: &mut W, format: ImageFormat, grid: &Array2D<bool>) -> io::Result<()> {
+    let (magic, on, off) = match format {
+        ImageFormat::Pbm => ("P1", "1", "0"),
+        ImageFormat::Pgm => ("P2", "0", "255"),
+    };
+    writeln!(out, "{}", magic)?;
+    writeln!(out, "{} {}", grid.num_columns(), grid.num_rows())?;
+    if format == ImageFormat::Pgm {
+        writeln!(out, "255")?;
+    }
+    for row in grid.rows_iter() {
+        writeln!(
+            out,
+            "{}",
+            row.map(|lit| if *lit { on } else { off }).join(" ")
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum ImageError {
+    Io(io::Error),
+    BadMagic(String),
+    /// A header field or pixel was not a valid number.
+    BadNumber {
+        position: usize,
+    },
+    /// The header's pixel or byte count overflows a `usize`.
+    TooLarge {
+        rows: usize,
+        columns: usize,
+    },
+    UnexpectedEnd,
+}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> ImageError {
+        ImageError::Io(error)
+    }
+}
+
+/// Reads a plain or raw PBM or PGM image. Graymap pixels darker than half
+/// the maximum value are lit.
+fn read_image<R: Read>(input: &mut R) -> Result<Array2D<bool>, ImageError> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+    let mut position = 0;
+    let skip_space = |position: &mut usize| {
+        while *position < bytes.len() {
+            match bytes[*position] {
+                b'#' => {
+                    while *position < bytes.len() && bytes[*position] != b'\n' {
+                        *position += 1;
+                    }
+                }
+                b if b.is_ascii_whitespace() => *position += 1,
+                _ => break,
+            }
+        }
+    };
+    // A decimal number, or a single digit for plain bitmap pixels.
+    let number = |position: &mut usize, single_digit: bool| {
+        skip_space(position);
+        let start = *position;
+        while *position < bytes.len()
+            && bytes[*position].is_ascii_digit()
+            && !(single_digit && *position > start)
+        {
+            *position += 1;
+        }
+        if start == bytes.len() {
+            return Err(ImageError::UnexpectedEnd);
+        }
+        std::str::from_utf8(&bytes[start..*position])
+            .unwrap()
+            .parse::<usize>()
+            .map_err(|_| ImageError::BadNumber { position: start })
+    };
+
+    skip_space(&mut position);
+    let magic =
+        String::from_utf8_lossy(&bytes[position..(position + 2).min(bytes.len())]).to_string();
+    position += 2;
+    if !["P1", "P2", "P4", "P5"].contains(&magic.as_str()) {
+        return Err(ImageError::BadMagic(magic));
+    }
+    let columns = number(&mut position, false)?;
+    let rows = number(&mut position, false)?;
+    let max_value = if magic == "P2" || magic == "P5" {
+        number(&mut position, false)?
+    } else {
+        1
+    };
+    // Raw pixel data starts after a single whitespace byte.
+    let data = position + 1;
+    let raw = |index: usize| {
+        bytes
+            .get(data + index)
+            .copied()
+            .ok_or(ImageError::UnexpectedEnd)
+    };
+
+    // Check the header against the data before allocating for it. Plain
+    // pixels take at least a byte each.
+    let size = rows
+        .checked_mul(columns)
+        .ok_or(ImageError::TooLarge { rows, columns })?;
+    let (needed, available) = match magic.as_str() {
+        "P1" | "P2" => (Some(size), bytes.len().saturating_sub(position)),
+        "P4" => (
+            rows.checked_mul((columns + 7) / 8),
+            bytes.len().saturating_sub(data),
+        ),
+        _ => (
+            size.checked_mul(if max_value < 256 { 1 } else { 2 }),
+            bytes.len().saturating_sub(data),
+        ),
+    };
+    if needed.ok_or(ImageError::TooLarge { rows, columns })? > available {
+        return Err(ImageError::UnexpectedEnd);
+    }
+
+    let mut pixels = Vec::with_capacity(size);
+    match magic.as_str() {
+        "P1" => {
+            for _ in 0..size {
+                skip_space(&mut position);
+                let start = position;
+                pixels.push(match number(&mut position, true)? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(ImageError::BadNumber { position: start }),
+                });
+            }
+        }
+        "P2" => {
+            for _ in 0..size {
+                pixels.push(number(&mut position, false)? * 2 < max_value);
+            }
+        }
+        "P4" => {
+            let row_bytes = (columns + 7) / 8;
+            for (row, col) in iproduct!(0..rows, 0..columns) {
+                pixels.push(raw(row * row_bytes + col / 8)? >> (7 - col % 8) & 1 == 1);
+            }
+        }
+        _ => {
+            let width = if max_value < 256 { 1 } else { 2 };
+            for i in 0..size {
+                let value = (0..width).try_fold(0, |value, k| {
+                    Ok::<_, ImageError>(value << 8 | raw(i * width + k)? as usize)
+                })?;
+                pixels.push(value * 2 < max_value);
+            }
+        }
+    }
+    Ok(Array2D::from_row_major(&pixels, rows, columns))
+}
+
 #[test]
 fn part1() {
     let (key, grid) = load_grid();
-    println!("key.size {}", key.len());
-    let step1 = step(&key, &grid, false);
-    let step2 = step(&key, &step1.0, step1.1);
-    println!(
-        "lit {}",
-        step2.0.elements_row_major_iter().filter(|b| **b).count()
-    );
+    let mut automaton = enhancement_automaton(key, &grid);
+    automaton.step();
+    automaton.step();
+    println!("lit {}", automaton.count(&true).unwrap());
 }
 
 #[test]
 fn part2() {
     let (key, grid) = load_grid();
-    println!("key.size {}", key.len());
-    let mut it = (grid, false);
-    for _ in 0..50 {
-        it = step(&key, &it.0, it.1);
-    }
-    println!(
-        "lit {}",
-        it.0.elements_row_major_iter().filter(|b| **b).count()
+    let mut automaton = enhancement_automaton(key, &grid);
+    for generation in 0..=50 {
+        if generation > 0 {
+            automaton.step();
+        }
+        match automaton.count(&true) {
+            Some(count) => println!("generation {} lit {}", generation, count),
+            None => println!("generation {} lit infinitely many", generation),
+        }
+    }
+}
+
+#[test]
+fn animation() {
+    let (key, grid) = load_grid();
+    let dir = frames_dir("20_frames");
+    let paths = Enhancer::new(key, 1)
+        .animate(&grid, 50, &dir, ImageFormat::Pbm)
+        .unwrap();
+    println!("wrote {} frames to {}", paths.len(), dir.display());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A fresh directory for frames, unique to this process so that parallel
+/// runs do not share files.
+#[cfg(test)]
+fn frames_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_enhancer_matches_step() {
+    let mut rng = crate::Lcg::new(20);
+    for _ in 0..20 {
+        let key = (0..512).map(|_| rng.range(0, 1) == 1).collect_vec();
+        let (rows, columns) = (rng.range(1, 8) as usize, rng.range(1, 70) as usize);
+        let grid = Array2D::from_row_major(
+            &(0..rows * columns)
+                .map(|_| rng.range(0, 1) == 1)
+                .collect_vec(),
+            rows,
+            columns,
+        );
+        let (image, lit) = Enhancer::new(key.clone(), 1).run(&grid, 4);
+        let mut expected = (grid, false);
+        for generation in 1..=4 {
+            expected = step(&key, &expected.0, expected.1);
+            let count = expected.0.elements_row_major_iter().filter(|b| **b).count();
+            assert_eq!(lit[generation], if expected.1 { None } else { Some(count) });
+        }
+        assert_eq!(image.to_grid(), expected.0);
+        assert_eq!(image.background, expected.1);
+    }
+}
+
+#[test]
+fn test_enhancement_automaton() {
+    let (key, grid) = load_grid();
+    let mut automaton = enhancement_automaton(key.clone(), &grid);
+    let mut expected = (grid, false);
+    for _ in 0..4 {
+        automaton.step();
+        expected = step(&key, &expected.0, expected.1);
+        assert_eq!(automaton.grid, expected.0);
+        assert_eq!(automaton.boundary, Boundary::Background(expected.1));
+    }
+}
+
+#[test]
+fn test_enhancer_radius() {
+    // With radius zero each pixel only sees itself, so this key inverts the
+    // image and the background together.
+    let grid = Array2D::from_rows(&[vec![true, false, false]]);
+    let (image, lit) = Enhancer::new(vec![true, false], 0).run(&grid, 3);
+    assert_eq!(lit, [Some(1), None, Some(1), None]);
+    assert_eq!(
+        image.to_grid(),
+        Array2D::from_rows(&[vec![false, true, true]])
     );
+
+    // A radius two key on a canvas more than a word wide, with and without
+    // a flipping background.
+    let mut rng = crate::Lcg::new(40);
+    let mut key = (0..1 << 25).map(|_| rng.range(0, 1) == 1).collect_vec();
+    let (rows, columns) = (5, 70);
+    let grid = Array2D::from_row_major(
+        &(0..rows * columns)
+            .map(|_| rng.range(0, 1) == 1)
+            .collect_vec(),
+        rows,
+        columns,
+    );
+    for flipping in [false, true] {
+        let last = key.len() - 1;
+        key[0] = flipping;
+        key[last] = !flipping;
+        let (image, lit) = Enhancer::new(key.clone(), 2).run(&grid, 3);
+        let mut expected = (grid.clone(), false);
+        for generation in 1..=3 {
+            expected = naive_step(&key, &expected.0, expected.1, 2);
+            let count = expected.0.elements_row_major_iter().filter(|b| **b).count();
+            assert_eq!(lit[generation], if expected.1 { None } else { Some(count) });
+        }
+        assert_eq!(image.to_grid(), expected.0);
+        assert_eq!(image.background, expected.1);
+    }
+}
+
+/// One enhancement with a neighborhood of any radius, pixel by pixel,
+/// growing the grid by `radius` on every side.
+#[cfg(test)]
+fn naive_step(
+    key: &[bool],
+    grid: &Array2D<bool>,
+    background: bool,
+    radius: i64,
+) -> (Array2D<bool>, bool) {
+    let (rows, columns) = (grid.num_rows() as i64, grid.num_columns() as i64);
+    let pixel = |row: i64, col: i64| {
+        if (0..rows).contains(&row) && (0..columns).contains(&col) {
+            grid[(row as usize, col as usize)]
+        } else {
+            background
+        }
+    };
+    let pixels = iproduct!(-radius..rows + radius, -radius..columns + radius)
+        .map(|(row, col)| {
+            key[iproduct!(-radius..=radius, -radius..=radius).fold(0, |index, (dr, dc)| {
+                index * 2 + pixel(row + dr, col + dc) as usize
+            })]
+        })
+        .collect_vec();
+    let next_background = key[if background { key.len() - 1 } else { 0 }];
+    (
+        Array2D::from_row_major(
+            &pixels,
+            (rows + 2 * radius) as usize,
+            (columns + 2 * radius) as usize,
+        ),
+        next_background,
+    )
+}
+
+#[test]
+fn test_image_formats() {
+    let grid = parse_grid("#..#.\n.##..\n....#\n");
+    assert_eq!(format_grid(&grid), "#..#.\n.##..\n....#\n");
+    for format in [ImageFormat::Pbm, ImageFormat::Pgm] {
+        let mut out = vec![];
+        write_image(&mut out, format, &grid).unwrap();
+        assert_eq!(read_image(&mut out.as_slice()).unwrap(), grid);
+    }
+
+    // Comments, and plain bitmap pixels without separators.
+    let plain = b"P1\n# a comment\n5 3\n10010\n01100 # another\n00001\n";
+    assert_eq!(read_image(&mut plain.as_slice()).unwrap(), grid);
+    let mut raw = b"P4 5 3\n".to_vec();
+    raw.extend([0b10010000, 0b01100000, 0b00001000]);
+    assert_eq!(read_image(&mut raw.as_slice()).unwrap(), grid);
+    let mut raw = b"P5 5 3 1000\n".to_vec();
+    for lit in grid.elements_row_major_iter() {
+        raw.extend(if *lit { [0, 100] } else { [3, 200] });
+    }
+    assert_eq!(read_image(&mut raw.as_slice()).unwrap(), grid);
+
+    assert!(matches!(
+        read_image(&mut b"P3 1 1 255 0 0 0".as_slice()),
+        Err(ImageError::BadMagic(_))
+    ));
+    assert!(matches!(
+        read_image(&mut b"P1 2 2 1 0 1".as_slice()),
+        Err(ImageError::UnexpectedEnd)
+    ));
+    assert!(matches!(
+        read_image(&mut b"P1 2 x 1 0 1 1".as_slice()),
+        Err(ImageError::BadNumber { position: 5 })
+    ));
+    assert!(matches!(
+        read_image(&mut b"P1 2 1 0 2".as_slice()),
+        Err(ImageError::BadNumber { position: 9 })
+    ));
+    // Huge headers are rejected before anything is allocated for them.
+    assert!(matches!(
+        read_image(&mut b"P4 100000 100000\n".as_slice()),
+        Err(ImageError::UnexpectedEnd)
+    ));
+    assert!(matches!(
+        read_image(&mut b"P1 4294967296 4294967296 0".as_slice()),
+        Err(ImageError::TooLarge {
+            rows: 4294967296,
+            columns: 4294967296
+        })
+    ));
+}
+
+#[test]
+fn test_animate() {
+    let key = (0..512).map(|i: u32| i.count_ones() % 2 == 1).collect_vec();
+    let grid = parse_grid("#..\n.#.\n..#\n");
+    let enhancer = Enhancer::new(key, 1);
+    let dir = frames_dir("20_test_frames");
+    let paths = enhancer.animate(&grid, 3, &dir, ImageFormat::Pgm).unwrap();
+    let mut frames = vec![];
+    enhancer.run_with(&grid, 3, |_, image| frames.push(image.to_grid()));
+    assert_eq!(paths.len(), 4);
+    for (path, frame) in paths.iter().zip(&frames) {
+        let mut file = File::open(path).unwrap();
+        assert_eq!(&read_image(&mut file).unwrap(), frame);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
 }
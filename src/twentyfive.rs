@@ -1,144 +1,385 @@
+use crate::automaton::{find_repeat, hash_state, Automaton, Boundary, Cycle, Phase, Update};
+use array2d::Array2D;
 use itertools::Itertools;
 use ndarray::Array2;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::{self, BufRead};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// A herd of sea cucumbers that all move the same way. Herds are passed
+/// around as slices and move one after another, in their order there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Herd {
+    symbol: char,
+    /// The move each cucumber tries to make, as `[rows, columns]`.
+    direction: [i64; 2],
+}
+
+/// The puzzle's herds, in the order they move: east, then south.
+fn puzzle_herds() -> Vec<Herd> {
+    vec![
+        Herd {
+            symbol: '>',
+            direction: [0, 1],
+        },
+        Herd {
+            symbol: 'v',
+            direction: [1, 0],
+        },
+    ]
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 enum Tile {
     Empty,
-    Right,
-    Down,
+    /// A cucumber of the herd with this index.
+    Herd(usize),
 }
 
 impl Tile {
-    fn parse(c: char) -> Tile {
+    /// The tile for `c`, or `None` if it is neither empty nor the symbol of
+    /// one of `herds`.
+    fn parse(c: char, herds: &[Herd]) -> Option<Tile> {
         match c {
-            '.' => Tile::Empty,
-            '>' => Tile::Right,
-            'v' => Tile::Down,
-            _ => panic!(),
+            '.' => Some(Tile::Empty),
+            _ => herds.iter().position(|h| h.symbol == c).map(Tile::Herd),
         }
     }
 }
 
-fn load_grid() -> Array2<Tile> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GridError {
+    UnknownSymbol {
+        row: usize,
+        column: usize,
+        symbol: char,
+    },
+    /// A line, counting from 0 like rows, not as wide as the first.
+    Ragged {
+        line: usize,
+    },
+    /// No lines, or a first line with no tiles.
+    Empty,
+}
+
+fn load_grid(herds: &[Herd]) -> Array2<Tile> {
     let file = File::open("25.txt").unwrap();
     let lines: io::Lines<io::BufReader<File>> = io::BufReader::new(file).lines();
+    parse_grid(lines.map(|l| l.unwrap()), herds).unwrap()
+}
+
+fn parse_grid<I: Iterator<Item = String>>(
+    lines: I,
+    herds: &[Herd],
+) -> Result<Array2<Tile>, GridError> {
     let arr = lines
-        .map(|l| l.unwrap().chars().map(Tile::parse).collect_vec())
-        .collect_vec();
-    return ndarray::Array2::from_shape_fn((arr.len(), arr[0].len()), |(i, j)| arr[i][j]);
+        .enumerate()
+        .map(|(row, l)| {
+            l.chars()
+                .enumerate()
+                .map(|(column, symbol)| {
+                    Tile::parse(symbol, herds).ok_or(GridError::UnknownSymbol {
+                        row,
+                        column,
+                        symbol,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns = arr.first().ok_or(GridError::Empty)?.len();
+    if columns == 0 {
+        return Err(GridError::Empty);
+    }
+    if let Some(line) = arr.iter().position(|row| row.len() != columns) {
+        return Err(GridError::Ragged { line });
+    }
+    return Ok(ndarray::Array2::from_shape_fn(
+        (arr.len(), columns),
+        |(i, j)| arr[i][j],
+    ));
 }
 
+#[derive(Clone)]
 struct Board {
     grid: Array2<Tile>,
-    active_right: HashSet<[usize; 2]>,
-    active_down: HashSet<[usize; 2]>,
+    /// The herds, in the order they move within a step.
+    herds: Vec<Herd>,
+    /// For each herd, the cucumbers that might be able to move.
+    active: Vec<HashSet<[usize; 2]>>,
 }
 
 impl Board {
-    fn from_grid(grid: &Array2<Tile>) -> Board {
+    fn from_grid(grid: &Array2<Tile>, herds: &[Herd]) -> Board {
         Board {
             grid: grid.clone(),
-            active_right: grid
-                .indexed_iter()
-                .filter_map(|((row, col), t)| match t {
-                    Tile::Right => Some([row, col]),
-                    _ => None,
+            herds: herds.to_vec(),
+            active: (0..herds.len())
+                .map(|herd| {
+                    grid.indexed_iter()
+                        .filter(|(_, t)| **t == Tile::Herd(herd))
+                        .map(|((row, col), _)| [row, col])
+                        .collect()
                 })
                 .collect(),
-            active_down: grid
-                .indexed_iter()
-                .filter_map(|((row, col), t)| match t {
-                    Tile::Down => Some([row, col]),
-                    _ => None,
-                })
-                .collect(),
-        }
-    }
-    fn get_queue(&self, direction: Tile) -> &HashSet<[usize; 2]> {
-        match direction {
-            Tile::Right => &self.active_right,
-            Tile::Down => &self.active_down,
-            Tile::Empty => panic!(),
         }
     }
 
-    fn get_queue_mut(&mut self, direction: Tile) -> &mut HashSet<[usize; 2]> {
-        match direction {
-            Tile::Right => &mut self.active_right,
-            Tile::Down => &mut self.active_down,
-            Tile::Empty => panic!(),
-        }
-    }
-    fn next(&self, ind: &[usize; 2]) -> [usize; 2] {
-        match self.grid[*ind] {
-            Tile::Right => self.right(ind),
-            Tile::Down => self.down(ind),
-            _ => panic!(),
-        }
-    }
-    fn right(&self, ind: &[usize; 2]) -> [usize; 2] {
-        [ind[0], (ind[1] + 1) % self.grid.ncols()]
+    /// The cell `direction` away from `ind`, wrapping around the edges.
+    fn offset(&self, ind: &[usize; 2], direction: [i64; 2]) -> [usize; 2] {
+        let (rows, cols) = (self.grid.nrows() as i64, self.grid.ncols() as i64);
+        [
+            (ind[0] as i64 + direction[0]).rem_euclid(rows) as usize,
+            (ind[1] as i64 + direction[1]).rem_euclid(cols) as usize,
+        ]
     }
 
-    fn down(&self, ind: &[usize; 2]) -> [usize; 2] {
-        [(ind[0] + 1) % self.grid.nrows(), ind[1]]
-    }
-    fn left(&self, ind: &[usize; 2]) -> [usize; 2] {
-        [ind[0], (ind[1] + self.grid.ncols() - 1) % self.grid.ncols()]
-    }
-    fn up(&self, ind: &[usize; 2]) -> [usize; 2] {
-        [(ind[0] + self.grid.nrows() - 1) % self.grid.nrows(), ind[1]]
+    fn render(&self) -> Vec<String> {
+        self.grid
+            .rows()
+            .into_iter()
+            .map(|row| {
+                row.iter()
+                    .map(|t| match t {
+                        Tile::Empty => '.',
+                        Tile::Herd(herd) => self.herds[*herd].symbol,
+                    })
+                    .collect()
+            })
+            .collect()
     }
 }
 
 fn step(board: &mut Board) -> bool {
     let mut changed = false;
-    for direction in [Tile::Right, Tile::Down] {
-        let stepped = board
-            .get_queue(direction)
+    for herd in 0..board.herds.len() {
+        let direction = board.herds[herd].direction;
+        let stepped = board.active[herd]
             .iter()
             .cloned()
-            .filter(|&ind| board.grid[board.next(&ind)] == Tile::Empty)
+            .filter(|ind| board.grid[board.offset(ind, direction)] == Tile::Empty)
             .collect_vec();
-        board.get_queue_mut(direction).clear();
+        board.active[herd].clear();
         for start in stepped {
             changed = true;
-            let direction = board.grid[start];
-            let end = board.next(&start);
+            let end = board.offset(&start, direction);
             board.grid[start] = Tile::Empty;
-            board.grid[end] = direction;
-            board.get_queue_mut(direction).insert(end);
-            let above = board.up(&start);
-            if board.grid[above] == Tile::Down {
-                board.active_down.insert(above);
-            }
-            let left = board.left(&start);
-            if board.grid[left] == Tile::Right {
-                board.active_right.insert(left);
+            board.grid[end] = Tile::Herd(herd);
+            board.active[herd].insert(end);
+            // Wake up any cucumber that was waiting to move into `start`.
+            for other in 0..board.herds.len() {
+                let behind = board.offset(&start, board.herds[other].direction.map(|d| -d));
+                if board.grid[behind] == Tile::Herd(other) {
+                    board.active[other].insert(behind);
+                }
             }
         }
     }
     return changed;
 }
+
+/// How a simulation ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// Nothing moved on step `step`, counting from 1.
+    Stopped { step: usize },
+    /// The grid after `first_repeat` steps was already seen `period` steps
+    /// earlier, so the herds move forever.
+    Cycle { first_repeat: usize, period: usize },
+    /// The herds were still moving after `steps` steps.
+    Unfinished { steps: usize },
+}
+
+/// Steps `board` until the herds stop, revisit an earlier grid, or
+/// `max_steps` steps have run. A grid that repeats straight away is one
+/// where nothing moved.
+fn simulate(board: &mut Board, max_steps: usize) -> Outcome {
+    let cycle = find_repeat(
+        board,
+        max_steps,
+        |board| {
+            step(board);
+        },
+        |board| hash_state(&board.grid),
+        |a, b| a.grid == b.grid,
+    );
+    match cycle {
+        Some(Cycle { start, period: 1 }) => Outcome::Stopped { step: start + 1 },
+        Some(Cycle { start, period }) => Outcome::Cycle {
+            first_repeat: start + period,
+            period,
+        },
+        None => Outcome::Unfinished { steps: max_steps },
+    }
+}
+
+/// The herds as a phased automaton on a torus, equivalent to `step`, with
+/// one phase per herd in the order of `herds`.
+fn cucumber_automaton(grid: &Array2<Tile>, herds: &[Herd]) -> Automaton<Tile> {
+    Automaton::new(
+        Array2D::from_row_major(
+            &grid.iter().cloned().collect_vec(),
+            grid.nrows(),
+            grid.ncols(),
+        ),
+        Boundary::Toroidal,
+        Update::Phased(
+            herds
+                .iter()
+                .enumerate()
+                .map(|(herd, h)| Phase {
+                    mover: Tile::Herd(herd),
+                    offset: h.direction,
+                    empty: Tile::Empty,
+                })
+                .collect(),
+        ),
+    )
+}
+
 #[test]
 fn part1() {
-    let grid = load_grid();
-    let mut board = Board::from_grid(&grid);
-    println!("{:?}", grid);
+    let grid = load_grid(&puzzle_herds());
+    let mut automaton = cucumber_automaton(&grid, &puzzle_herds());
+    match automaton.run_until_stable(1000000) {
+        Some(step) => println!("steps till stop {}", step),
+        None => println!("never stopped"),
+    }
+}
+
+#[test]
+fn test_cucumber_automaton() {
+    let grid = load_grid(&puzzle_herds());
+    let mut board = Board::from_grid(&grid, &puzzle_herds());
     let mut steps = 1;
     while step(&mut board) {
         steps += 1;
-        if steps % 100 == 0 {
-            println!(
-                "Step {} {} {}",
-                steps,
-                board.active_right.len(),
-                board.active_down.len()
+    }
+    let mut automaton = cucumber_automaton(&grid, &puzzle_herds());
+    assert_eq!(automaton.run_until_stable(10000), Some(steps));
+    assert_eq!(
+        automaton
+            .grid
+            .elements_row_major_iter()
+            .cloned()
+            .collect_vec(),
+        board.grid.iter().cloned().collect_vec()
+    );
+}
+
+#[test]
+fn test_simulate() {
+    let example = [
+        "v...>>.vv>",
+        ".vv>>.vv..",
+        ">>.>v>...v",
+        ">>v>>.>.v.",
+        "v>v.vv.v..",
+        ">.>>..v...",
+        ".vv..>.>v.",
+        "v.v..>>v.v",
+        "....v..v.>",
+    ];
+    let grid = parse_grid(example.iter().map(|l| l.to_string()), &puzzle_herds()).unwrap();
+    let mut board = Board::from_grid(&grid, &puzzle_herds());
+    assert_eq!(simulate(&mut board, 10), Outcome::Unfinished { steps: 10 });
+    let mut board = Board::from_grid(&grid, &puzzle_herds());
+    assert_eq!(simulate(&mut board, 100), Outcome::Stopped { step: 58 });
+
+    // A lone cucumber in a row goes round forever.
+    let grid = parse_grid(
+        ["..>".to_string(), "...".to_string()].into_iter(),
+        &puzzle_herds(),
+    )
+    .unwrap();
+    let mut board = Board::from_grid(&grid, &puzzle_herds());
+    assert_eq!(
+        simulate(&mut board, 100),
+        Outcome::Cycle {
+            first_repeat: 3,
+            period: 3
+        }
+    );
+    // The right mover is blocked on the first step only, so the cycle
+    // starts after one step.
+    let grid = parse_grid(
+        [">v.", "...", "..."].iter().map(|l| l.to_string()),
+        &puzzle_herds(),
+    )
+    .unwrap();
+    let mut board = Board::from_grid(&grid, &puzzle_herds());
+    assert_eq!(
+        simulate(&mut board, 100),
+        Outcome::Cycle {
+            first_repeat: 4,
+            period: 3
+        }
+    );
+}
+
+#[test]
+fn test_custom_herds() {
+    let herds = [
+        ('>', [0, 1]),
+        ('<', [0, -1]),
+        ('^', [-1, 0]),
+        ('v', [1, 0]),
+        ('/', [-1, 1]),
+        ('\\', [1, 1]),
+    ]
+    .iter()
+    .map(|&(symbol, direction)| Herd { symbol, direction })
+    .collect_vec();
+    let mut rng = crate::Lcg::new(25);
+    for _ in 0..20 {
+        let (rows, cols) = (rng.range(1, 8), rng.range(1, 8));
+        let lines = (0..rows)
+            .map(|_| {
+                (0..cols)
+                    .map(|_| match rng.range(0, 2 * herds.len() as i64) as usize {
+                        herd if herd < herds.len() => herds[herd].symbol,
+                        _ => '.',
+                    })
+                    .collect::<String>()
+            })
+            .collect_vec();
+        let grid = parse_grid(lines.iter().cloned(), &herds).unwrap();
+        let mut board = Board::from_grid(&grid, &herds);
+        assert_eq!(board.render(), lines);
+        let mut automaton = cucumber_automaton(&grid, &herds);
+        for _ in 0..30 {
+            assert_eq!(step(&mut board), automaton.step() > 0);
+            assert_eq!(
+                board.grid.iter().cloned().collect_vec(),
+                automaton.grid.as_row_major()
             );
         }
     }
-    println!("steps till stop {}", steps);
+
+    // Herds moving west then north, the reverse of the puzzle.
+    let herds = [('<', [0, -1]), ('^', [-1, 0])]
+        .iter()
+        .map(|&(symbol, direction)| Herd { symbol, direction })
+        .collect_vec();
+    let grid = parse_grid(["^..", "<.<", "..^"].iter().map(|l| l.to_string()), &herds).unwrap();
+    let mut board = Board::from_grid(&grid, &herds);
+    step(&mut board);
+    assert_eq!(board.render(), ["...", "<<^", "^.."]);
+    // The puzzle's symbols mean nothing to these herds.
+    assert_eq!(
+        parse_grid(["^..", "<.>"].iter().map(|l| l.to_string()), &herds),
+        Err(GridError::UnknownSymbol {
+            row: 1,
+            column: 2,
+            symbol: '>'
+        })
+    );
+    assert_eq!(
+        parse_grid(["..^", "."].iter().map(|l| l.to_string()), &herds),
+        Err(GridError::Ragged { line: 1 })
+    );
+    assert_eq!(
+        parse_grid(std::iter::empty(), &herds),
+        Err(GridError::Empty)
+    );
 }
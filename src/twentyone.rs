@@ -1,120 +1,973 @@
+use crate::Lcg;
+use itertools::Itertools;
+use num::bigint::BigInt;
+use num::rational::BigRational;
+use num::{One, Zero};
+use regex::Regex;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::rc::Rc;
 
-struct DeterministicDie {
-    value: i128,
+#[derive(Debug, PartialEq, Eq)]
+enum StartError {
+    /// A line, counting from 1, not of the form
+    /// `Player N starting position: K`.
+    BadLine {
+        line: usize,
+    },
+    /// A player listed out of order; players must be numbered 1, 2, ...
+    WrongPlayer {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A starting position off a board with spaces `1..=board_size`.
+    OutOfRange {
+        line: usize,
+        position: i64,
+    },
+    NoPlayers,
+}
+
+fn load_starts(board_size: i64) -> Vec<i64> {
+    let file = File::open("21.txt").unwrap();
+    let lines: io::Lines<io::BufReader<File>> = io::BufReader::new(file).lines();
+    parse_starts(&lines.map(|l| l.unwrap()).join("\n"), board_size).unwrap()
+}
+
+/// Parses each player's starting position on a board with spaces
+/// `1..=board_size`, ignoring blank lines.
+fn parse_starts(input: &str, board_size: i64) -> Result<Vec<i64>, StartError> {
+    let re = Regex::new(r"^Player (\d+) starting position: (\d+)$").unwrap();
+    let mut starts = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let captures = re
+            .captures(line)
+            .ok_or(StartError::BadLine { line: i + 1 })?;
+        let number = |k: usize| {
+            captures[k]
+                .parse()
+                .map_err(|_| StartError::BadLine { line: i + 1 })
+        };
+        let player: usize = number(1)?;
+        if player != starts.len() + 1 {
+            return Err(StartError::WrongPlayer {
+                line: i + 1,
+                expected: starts.len() + 1,
+                found: player,
+            });
+        }
+        let position = number(2)? as i64;
+        if !(1..=board_size).contains(&position) {
+            return Err(StartError::OutOfRange {
+                line: i + 1,
+                position,
+            });
+        }
+        starts.push(position);
+    }
+    if starts.is_empty() {
+        return Err(StartError::NoPlayers);
+    }
+    Ok(starts)
 }
 
 trait Die {
-    fn roll(&mut self) -> i128;
+    fn roll(&mut self) -> i64;
+    /// How many times the die has been rolled.
+    fn rolls(&self) -> usize;
+}
+
+/// Rolls 1, 2, 3, ... up to `faces`, then starts again at 1.
+struct DeterministicDie {
+    value: i64,
+    faces: i64,
+    rolls: usize,
+}
+
+impl DeterministicDie {
+    fn new(faces: i64) -> DeterministicDie {
+        DeterministicDie {
+            value: 1,
+            faces,
+            rolls: 0,
+        }
+    }
 }
 
 impl Die for DeterministicDie {
-    fn roll(&mut self) -> i128 {
+    fn roll(&mut self) -> i64 {
         let result = self.value;
-        self.value += 1;
+        self.value = self.value % self.faces + 1;
+        self.rolls += 1;
         return result;
     }
+
+    fn rolls(&self) -> usize {
+        self.rolls
+    }
+}
+
+/// A fair die driven by a seeded linear congruential generator, so games
+/// can be replayed.
+struct RandomDie {
+    rng: Lcg,
+    faces: i64,
+    rolls: usize,
 }
 
-fn turn<T: Die>(position: &mut i128, score: &mut i128, die: &mut T) {
-    for _ in 0..3 {
-        *position += die.roll();
-        *position = (*position - 1) % 10 + 1;
+impl RandomDie {
+    fn new(faces: i64, seed: u64) -> RandomDie {
+        RandomDie {
+            rng: Lcg::new(seed),
+            faces,
+            rolls: 0,
+        }
     }
-    *score += *position;
-    println!("score {}", *score);
 }
-fn play_game<T: Die>(mut start1: i128, mut start2: i128, die: &mut T) -> (i128, i128) {
-    let mut player1_score = 0;
-    let mut player2_score = 0;
-    for i in 0..1000 {
-        turn(&mut start1, &mut player1_score, die);
-        if player1_score >= 1000 {
-            return (player2_score, i * 2 + 1);
+
+impl Die for RandomDie {
+    fn roll(&mut self) -> i64 {
+        self.rolls += 1;
+        self.rng.range(1, self.faces)
+    }
+
+    fn rolls(&self) -> usize {
+        self.rolls
+    }
+}
+
+/// A die that rolls each face with probability proportional to its weight.
+struct LoadedDie {
+    /// Each face with its weight.
+    faces: Vec<(i64, u64)>,
+    random: RandomDie,
+}
+
+impl LoadedDie {
+    fn new(faces: Vec<(i64, u64)>, seed: u64) -> LoadedDie {
+        let total: u64 = faces.iter().map(|(_, weight)| weight).sum();
+        assert!(total > 0, "loaded die needs a face with positive weight");
+        LoadedDie {
+            faces,
+            random: RandomDie::new(total as i64, seed),
         }
-        turn(&mut start2, &mut player2_score, die);
-        if player2_score >= 1000 {
-            return (player1_score, i * 2 + 2);
+    }
+}
+
+impl Die for LoadedDie {
+    fn roll(&mut self) -> i64 {
+        let mut pick = self.random.roll() as u64 - 1;
+        for (face, weight) in &self.faces {
+            if pick < *weight {
+                return *face;
+            }
+            pick -= weight;
         }
+        unreachable!()
     }
-    panic!();
+
+    fn rolls(&self) -> usize {
+        self.random.rolls()
+    }
+}
+
+/// One turn of a practice game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TurnRecord {
+    player: usize,
+    /// The total of the turn's rolls.
+    roll: i64,
+    position: i64,
+    score: i64,
+}
+
+/// How a practice game went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GameStats {
+    winner: usize,
+    positions: Vec<i64>,
+    scores: Vec<i64>,
+    turns: usize,
+    rolls: usize,
+    history: Vec<TurnRecord>,
+}
+
+impl GameStats {
+    /// The lowest losing score times the number of rolls.
+    fn puzzle_answer(&self) -> i64 {
+        self.scores.iter().min().unwrap() * self.rolls as i64
+    }
+}
+
+fn turn<T: Die>(position: &mut i64, score: &mut i64, die: &mut T, rules: &GameRules) -> i64 {
+    let roll = (0..rules.rolls_per_turn).map(|_| die.roll()).sum();
+    *position = rules.advance(*position, roll);
+    *score += *position;
+    return roll;
+}
+
+/// Plays a game with a real die until someone reaches the target score.
+/// Any die will do; `GameRules::practice_die` builds the one the rules
+/// describe.
+fn play_game<T: Die>(starts: &[i64], rules: &GameRules, die: &mut T) -> GameStats {
+    assert_eq!(starts.len(), rules.players);
+    let initial_rolls = die.rolls();
+    let mut positions = starts.to_vec();
+    let mut scores = vec![0; rules.players];
+    let mut history = vec![];
+    for player in (0..rules.players).cycle() {
+        let roll = turn(&mut positions[player], &mut scores[player], die, rules);
+        history.push(TurnRecord {
+            player,
+            roll,
+            position: positions[player],
+            score: scores[player],
+        });
+        if scores[player] >= rules.target_score {
+            return GameStats {
+                winner: player,
+                positions,
+                scores,
+                turns: history.len(),
+                rolls: die.rolls() - initial_rolls,
+                history,
+            };
+        }
+    }
+    unreachable!()
 }
 
 #[test]
 fn part1() {
-    let mut die = DeterministicDie { value: 1 };
-    let (score, turns) = play_game(4, 2, &mut die);
-    println!("{} {} {} {}", score, turns, turns * 3, score * turns * 3);
+    let starts = load_starts(PRACTICE_RULES.board_size);
+    let rules = GameRules {
+        players: starts.len(),
+        ..PRACTICE_RULES
+    };
+    let stats = play_game(&starts, &rules, &mut rules.practice_die());
+    println!(
+        "winner {} scores {:?} turns {} rolls {}",
+        stats.winner + 1,
+        stats.scores,
+        stats.turns,
+        stats.rolls
+    );
+    println!("{}", stats.puzzle_answer());
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+/// The rules of a game of Dirac Dice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GameRules {
+    players: usize,
+    /// Spaces on the circular board, numbered from 1.
+    board_size: i64,
+    die_faces: i64,
+    rolls_per_turn: usize,
+    target_score: i64,
+}
+
+const DIRAC_RULES: GameRules = GameRules {
+    players: 2,
+    board_size: 10,
+    die_faces: 3,
+    rolls_per_turn: 3,
+    target_score: 21,
+};
+
+/// The practice game, played with a deterministic 100-sided die.
+const PRACTICE_RULES: GameRules = GameRules {
+    players: 2,
+    board_size: 10,
+    die_faces: 100,
+    rolls_per_turn: 3,
+    target_score: 1000,
+};
+
+impl GameRules {
+    /// For each total of a turn's rolls, the number of ways to roll it.
+    fn roll_distribution(&self) -> Vec<(i64, i128)> {
+        let mut distribution = HashMap::from([(0, 1)]);
+        for _ in 0..self.rolls_per_turn {
+            let mut next = HashMap::new();
+            for (total, ways) in distribution {
+                for face in 1..=self.die_faces {
+                    *next.entry(total + face).or_insert(0) += ways;
+                }
+            }
+            distribution = next;
+        }
+        distribution.into_iter().sorted().collect()
+    }
+
+    /// A deterministic die with the rules' number of faces.
+    fn practice_die(&self) -> DeterministicDie {
+        DeterministicDie::new(self.die_faces)
+    }
+
+    /// The space `roll` spaces on from `position`, going backwards for a
+    /// negative roll.
+    fn advance(&self, position: i64, roll: i64) -> i64 {
+        (position + roll - 1).rem_euclid(self.board_size) + 1
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 struct GameState {
-    position1: i8,
-    position2: i8,
-    score1: i128,
-    score2: i128,
+    positions: Vec<i64>,
+    scores: Vec<i64>,
 }
 
-fn step_world(worlds: &HashMap<GameState, i128>) -> (HashMap<GameState, i128>, i128, i128) {
-    let mut player1_wins = 0;
-    let mut player2_wins = 0;
-    let roll_distribution = [0, 0, 0, 1, 3, 6, 7, 6, 3, 1];
+impl GameState {
+    fn new(starts: &[i64]) -> GameState {
+        GameState {
+            positions: starts.to_vec(),
+            scores: vec![0; starts.len()],
+        }
+    }
+}
+
+/// Plays one turn for `player` in every world, passing each finished
+/// world and its number of universes to `on_win` and returning the rest.
+fn step_turn<F: FnMut(&GameState, i128)>(
+    worlds: &HashMap<GameState, i128>,
+    player: usize,
+    rules: &GameRules,
+    mut on_win: F,
+) -> HashMap<GameState, i128> {
+    let roll_distribution = rules.roll_distribution();
     let mut result = HashMap::new();
     for (state, count) in worlds.iter() {
-        for (roll1, prob1) in roll_distribution.iter().enumerate() {
-            if *prob1 == 0 {
-                continue;
+        for (roll, ways) in &roll_distribution {
+            let mut next = state.clone();
+            next.positions[player] = rules.advance(state.positions[player], *roll);
+            next.scores[player] += next.positions[player];
+            if next.scores[player] >= rules.target_score {
+                on_win(&next, count * ways);
+            } else {
+                *result.entry(next).or_insert(0) += count * ways;
+            }
+        }
+    }
+    return result;
+}
+
+/// Plays one round, in which every player takes a turn, returning the
+/// unfinished worlds and the worlds each player won.
+fn step_world(
+    worlds: &HashMap<GameState, i128>,
+    rules: &GameRules,
+) -> (HashMap<GameState, i128>, Vec<i128>) {
+    let mut wins = vec![0; rules.players];
+    let mut result = worlds.clone();
+    for player in 0..rules.players {
+        result = step_turn(&result, player, rules, |_, count| wins[player] += count);
+    }
+    return (result, wins);
+}
+
+/// The number of universes each player wins in, starting from `starts`.
+fn dirac_wins(rules: &GameRules, starts: &[i64]) -> Vec<i128> {
+    assert_eq!(starts.len(), rules.players);
+    let mut worlds = HashMap::from([(GameState::new(starts), 1)]);
+    let mut total_wins = vec![0; rules.players];
+    while !worlds.is_empty() {
+        let (new_worlds, wins) = step_world(&worlds, rules);
+        worlds = new_worlds;
+        for (total, w) in total_wins.iter_mut().zip(wins) {
+            *total += w;
+        }
+    }
+    return total_wins;
+}
+
+fn big(n: i128) -> BigInt {
+    (BigInt::from((n >> 64) as i64) << 64) + BigInt::from(n as u64)
+}
+
+/// How the games from some state end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endings {
+    /// `wins[player][turns]` is the number of universes in which `player`
+    /// wins after `turns` more turns.
+    wins: Vec<Vec<i128>>,
+}
+
+/// Counts how games end by recursing over game states, remembering the
+/// answer for every state it has seen.
+struct DiracSolver<'a> {
+    rules: &'a GameRules,
+    roll_distribution: Vec<(i64, i128)>,
+    memo: HashMap<(GameState, usize), Rc<Endings>>,
+}
+
+impl<'a> DiracSolver<'a> {
+    fn new(rules: &'a GameRules) -> DiracSolver<'a> {
+        DiracSolver {
+            rules,
+            roll_distribution: rules.roll_distribution(),
+            memo: HashMap::new(),
+        }
+    }
+
+    /// How the games end from `state` with `player` to move.
+    fn endings(&mut self, state: &GameState, player: usize) -> Rc<Endings> {
+        let key = (state.clone(), player);
+        if let Some(endings) = self.memo.get(&key) {
+            return endings.clone();
+        }
+        let mut wins = vec![vec![]; self.rules.players];
+        let mut add = |winner: usize, turns: usize, count: i128| {
+            if wins[winner].len() <= turns {
+                wins[winner].resize(turns + 1, 0);
             }
-            let position1 = ((state.position1 as i128 + roll1 as i128 - 1) % 10 + 1) as i8;
-            let score1 = state.score1 + position1 as i128;
-            if score1 >= 21 {
-                player1_wins += count * prob1;
+            wins[winner][turns] += count;
+        };
+        for (roll, ways) in self.roll_distribution.clone() {
+            let mut next = state.clone();
+            next.positions[player] = self.rules.advance(state.positions[player], roll);
+            next.scores[player] += next.positions[player];
+            if next.scores[player] >= self.rules.target_score {
+                add(player, 1, ways);
             } else {
-                for (roll2, prob2) in roll_distribution.iter().enumerate() {
-                    if *prob2 == 0 {
-                        continue;
+                let rest = self.endings(&next, (player + 1) % self.rules.players);
+                for (winner, counts) in rest.wins.iter().enumerate() {
+                    for (turns, count) in counts.iter().enumerate() {
+                        add(winner, turns + 1, ways * count);
                     }
-                    let position2 = ((state.position2 as i128 + roll2 as i128 - 1) % 10 + 1) as i8;
-                    let score2 = state.score2 + position2 as i128;
-                    if score2 >= 21 {
-                        player2_wins += count * prob1 * prob2;
+                }
+            }
+        }
+        let endings = Rc::new(Endings { wins });
+        self.memo.insert(key, endings.clone());
+        endings
+    }
+}
+
+/// Everything about a game of Dirac Dice from its start.
+#[derive(Debug, Clone, PartialEq)]
+struct GameSummary {
+    /// The number of universes each player wins in.
+    wins: Vec<i128>,
+    /// The chance each player wins when the die is rolled for real.
+    win_probabilities: Vec<BigRational>,
+    /// The expected number of turns in the game.
+    expected_turns: BigRational,
+    /// The chance of every possible set of final scores.
+    final_scores: HashMap<Vec<i64>, BigRational>,
+}
+
+/// Adds up `counts[turns]` universes that each end after `turns` turns, as
+/// a chance of happening.
+fn probability(counts: &[i128], rules: &GameRules) -> BigRational {
+    // Every universe ending after `turns` turns has chance `1 / n^turns`,
+    // so put everything over the largest denominator.
+    let n = big(num::pow(rules.die_faces as i128, rules.rolls_per_turn));
+    let numerator = counts.iter().fold(BigInt::zero(), |total, count| {
+        total * n.clone() + big(*count)
+    });
+    BigRational::new(numerator, num::pow(n, counts.len().saturating_sub(1)))
+}
+
+fn add_counts(total: &mut Vec<i128>, counts: &[i128]) {
+    if total.len() < counts.len() {
+        total.resize(counts.len(), 0);
+    }
+    for (t, c) in total.iter_mut().zip(counts) {
+        *t += c;
+    }
+}
+
+fn solve(rules: &GameRules, starts: &[i64]) -> GameSummary {
+    assert_eq!(starts.len(), rules.players);
+    let start = GameState::new(starts);
+    let endings = DiracSolver::new(rules).endings(&start, 0);
+    let wins = endings.wins.iter().map(|w| w.iter().sum()).collect();
+    let win_probabilities = endings.wins.iter().map(|w| probability(w, rules)).collect();
+    let mut turns_weighted = vec![];
+    for w in &endings.wins {
+        let weighted = w
+            .iter()
+            .enumerate()
+            .map(|(turns, count)| count * turns as i128)
+            .collect_vec();
+        add_counts(&mut turns_weighted, &weighted);
+    }
+    let expected_turns = probability(&turns_weighted, rules);
+
+    // The final scores need the whole state, so play the worlds forward
+    // one turn at a time instead.
+    let mut final_counts: HashMap<Vec<i64>, Vec<i128>> = HashMap::new();
+    let mut worlds = HashMap::from([(start, 1)]);
+    let mut turns = 0;
+    while !worlds.is_empty() {
+        turns += 1;
+        worlds = step_turn(
+            &worlds,
+            (turns - 1) % rules.players,
+            rules,
+            |state, count| {
+                let counts = final_counts.entry(state.scores.clone()).or_default();
+                counts.resize(turns + 1, 0);
+                counts[turns] += count;
+            },
+        );
+    }
+    let final_scores = final_counts
+        .into_iter()
+        .map(|(scores, counts)| (scores, probability(&counts, rules)))
+        .collect();
+
+    GameSummary {
+        wins,
+        win_probabilities,
+        expected_turns,
+        final_scores,
+    }
+}
+
+/// A way a player may use a turn's roll in the strategy variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Choice {
+    /// Move forward by the roll, as in the normal game.
+    Forward,
+    /// Move backward by the roll.
+    Backward,
+    /// Throw the roll away and roll again, at most once per turn.
+    Reroll,
+}
+
+/// Finds optimal play when players choose what to do with their rolls, by
+/// expectimax: rolls are averaged over, and each player makes the choice
+/// that maximizes their own chance of winning. With two players that is
+/// the same as minimizing the opponent's chance, i.e. expectiminimax.
+///
+/// Chances are `f64` rather than the exact `BigRational`s of `solve`. Each
+/// state is visited once per reroll flag and compares every choice for
+/// every roll, and with denominators up to a power of 27 per turn played
+/// exact fractions would make each of those additions and comparisons a
+/// big-number operation. Without choices the two agree to within 1e-12.
+struct StrategySolver<'a> {
+    rules: &'a GameRules,
+    /// The choices open to each player.
+    choices: Vec<Vec<Choice>>,
+    roll_chances: Vec<(i64, f64)>,
+    memo: HashMap<(GameState, usize, bool), Rc<Vec<f64>>>,
+}
+
+impl<'a> StrategySolver<'a> {
+    fn new(rules: &'a GameRules, choices: Vec<Vec<Choice>>) -> StrategySolver<'a> {
+        assert_eq!(choices.len(), rules.players);
+        let outcomes = num::pow(rules.die_faces, rules.rolls_per_turn) as f64;
+        StrategySolver {
+            rules,
+            choices,
+            roll_chances: rules
+                .roll_distribution()
+                .into_iter()
+                .map(|(roll, ways)| (roll, ways as f64 / outcomes))
+                .collect(),
+            memo: HashMap::new(),
+        }
+    }
+
+    /// Each player's chance of winning under optimal play, from `state`
+    /// with `player` about to roll. `rerolled` says whether they already
+    /// rerolled this turn.
+    fn value(&mut self, state: &GameState, player: usize, rerolled: bool) -> Rc<Vec<f64>> {
+        let key = (state.clone(), player, rerolled);
+        if let Some(value) = self.memo.get(&key) {
+            return value.clone();
+        }
+        let mut value = vec![0.0; self.rules.players];
+        for (roll, chance) in self.roll_chances.clone() {
+            let (_, outcome) = self.decide(state, player, roll, rerolled);
+            for (v, o) in value.iter_mut().zip(outcome.iter()) {
+                *v += chance * o;
+            }
+        }
+        let value = Rc::new(value);
+        self.memo.insert(key, value.clone());
+        value
+    }
+
+    /// The best choice for `player` having rolled `roll`, and each player's
+    /// chance of winning after it.
+    fn decide(
+        &mut self,
+        state: &GameState,
+        player: usize,
+        roll: i64,
+        rerolled: bool,
+    ) -> (Choice, Rc<Vec<f64>>) {
+        let mut best: Option<(Choice, Rc<Vec<f64>>)> = None;
+        for choice in self.choices[player].clone() {
+            let outcome = match choice {
+                Choice::Reroll if rerolled => continue,
+                Choice::Reroll => self.value(state, player, true),
+                Choice::Forward | Choice::Backward => {
+                    let step = if choice == Choice::Forward {
+                        roll
+                    } else {
+                        -roll
+                    };
+                    let mut next = state.clone();
+                    next.positions[player] = self.rules.advance(state.positions[player], step);
+                    next.scores[player] += next.positions[player];
+                    if next.scores[player] >= self.rules.target_score {
+                        let mut won = vec![0.0; self.rules.players];
+                        won[player] = 1.0;
+                        Rc::new(won)
                     } else {
-                            * result
-                                .entry(GameState {
-                                    position1,
-                                    position2,
-                                    score1,
-                                    score2,
-                                })
-                                .or_insert(0) += count * prob1 * prob2;
+                        self.value(&next, (player + 1) % self.rules.players, false)
                     }
                 }
+            };
+            if best
+                .as_ref()
+                .map_or(true, |(_, b)| outcome[player] > b[player])
+            {
+                best = Some((choice, outcome));
             }
         }
+        best.expect("player has no usable choice")
     }
-    return (result, player1_wins, player2_wins);
 }
 
 #[test]
 fn part2() {
-    let mut worlds = HashMap::new();
-    worlds.insert(
-        GameState {
-            position1: 4,
-            position2: 2,
-            score1: 0,
-            score2: 0,
+    let starts = load_starts(DIRAC_RULES.board_size);
+    let rules = GameRules {
+        players: starts.len(),
+        ..DIRAC_RULES
+    };
+    let wins = dirac_wins(&rules, &starts);
+    println!("totalwins {}", wins.iter().join(" "));
+    let summary = solve(&rules, &starts);
+    for (player, probability) in summary.win_probabilities.iter().enumerate() {
+        println!(
+            "player {} wins with probability {}",
+            player + 1,
+            probability
+        );
+    }
+    println!("expected turns {}", summary.expected_turns);
+    println!("possible final scores {}", summary.final_scores.len());
+}
+
+#[test]
+fn strategy() {
+    let choices = vec![Choice::Forward, Choice::Backward, Choice::Reroll];
+    let mut solver = StrategySolver::new(&DIRAC_RULES, vec![choices.clone(), choices]);
+    let start = GameState::new(&load_starts(DIRAC_RULES.board_size));
+    let value = solver.value(&start, 0, false);
+    println!("win chances {:?}", value);
+    assert!((value.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    // The chances are the first player's best choices averaged over rolls.
+    let mut average = 0.0;
+    for (roll, ways) in DIRAC_RULES.roll_distribution() {
+        let (choice, outcome) = solver.decide(&start, 0, roll, false);
+        println!("first roll {} choose {:?}", roll, choice);
+        average += outcome[0] * ways as f64 / 27.0;
+    }
+    assert!((average - value[0]).abs() < 1e-9);
+    println!("states {}", solver.memo.len());
+}
+
+#[cfg(test)]
+fn brute_force_wins(rules: &GameRules, state: &mut GameState, player: usize, wins: &mut [i128]) {
+    let rolls = (0..rules.rolls_per_turn).map(|_| 1..=rules.die_faces);
+    for roll in rolls.multi_cartesian_product() {
+        let position = state.positions[player];
+        state.positions[player] = rules.advance(position, roll.iter().sum());
+        state.scores[player] += state.positions[player];
+        if state.scores[player] >= rules.target_score {
+            wins[player] += 1;
+        } else {
+            brute_force_wins(rules, state, (player + 1) % rules.players, wins);
+        }
+        state.scores[player] -= state.positions[player];
+        state.positions[player] = position;
+    }
+}
+
+#[test]
+fn test_dirac_wins() {
+    assert_eq!(
+        dirac_wins(&DIRAC_RULES, &[4, 8]),
+        [444356092776315, 341960390180808]
+    );
+    assert_eq!(
+        DIRAC_RULES.roll_distribution(),
+        [(3, 1), (4, 3), (5, 6), (6, 7), (7, 6), (8, 3), (9, 1)]
+    );
+    for (rules, starts) in [
+        (
+            GameRules {
+                players: 3,
+                board_size: 5,
+                die_faces: 2,
+                rolls_per_turn: 2,
+                target_score: 8,
+            },
+            vec![1, 3, 5],
+        ),
+        (
+            GameRules {
+                players: 1,
+                board_size: 7,
+                die_faces: 4,
+                rolls_per_turn: 1,
+                target_score: 12,
+            },
+            vec![2],
+        ),
+        (
+            GameRules {
+                players: 4,
+                board_size: 3,
+                die_faces: 3,
+                rolls_per_turn: 1,
+                target_score: 6,
+            },
+            vec![1, 2, 3, 1],
+        ),
+    ] {
+        let mut expected = vec![0; rules.players];
+        brute_force_wins(&rules, &mut GameState::new(&starts), 0, &mut expected);
+        assert_eq!(dirac_wins(&rules, &starts), expected);
+    }
+}
+
+#[test]
+fn test_solve() {
+    let summary = solve(&DIRAC_RULES, &[4, 8]);
+    assert_eq!(summary.wins, [444356092776315, 341960390180808]);
+    assert_eq!(summary.wins, dirac_wins(&DIRAC_RULES, &[4, 8]));
+    let total =
+        |values: Vec<&BigRational>| values.into_iter().fold(BigRational::zero(), |a, b| a + b);
+    assert_eq!(
+        total(summary.win_probabilities.iter().collect()),
+        BigRational::one()
+    );
+    assert_eq!(
+        total(summary.final_scores.values().collect()),
+        BigRational::one()
+    );
+    // Exactly one player reaches the target in every game.
+    for scores in summary.final_scores.keys() {
+        assert_eq!(scores.iter().filter(|s| **s >= 21).count(), 1);
+    }
+
+    // A single player on a two space board with a coin: the first roll
+    // moves them to space 2 or back to 1, and every score counts.
+    let rules = GameRules {
+        players: 1,
+        board_size: 2,
+        die_faces: 2,
+        rolls_per_turn: 1,
+        target_score: 3,
+    };
+    let summary = solve(&rules, &[1]);
+    // Scores go 2 then 3 or 4, or 1 then 3, or 1 then 2 then 3 or 4.
+    assert_eq!(summary.wins, [5]);
+    assert_eq!(summary.win_probabilities, [BigRational::one()]);
+    let ratio = |n: i64, d: i64| BigRational::new(BigInt::from(n), BigInt::from(d));
+    assert_eq!(summary.expected_turns, ratio(9, 4));
+    assert_eq!(
+        summary.final_scores,
+        HashMap::from([(vec![3], ratio(5, 8)), (vec![4], ratio(3, 8))])
+    );
+}
+
+#[test]
+fn test_dice() {
+    let mut die = DeterministicDie::new(100);
+    let rolls = (0..250).map(|_| die.roll()).collect_vec();
+    assert_eq!(rolls[..3], [1, 2, 3]);
+    assert_eq!(rolls[98..102], [99, 100, 1, 2]);
+    assert_eq!(rolls[249], 50);
+    assert_eq!(die.rolls(), 250);
+
+    let stats = play_game(&[4, 8], &PRACTICE_RULES, &mut PRACTICE_RULES.practice_die());
+    assert_eq!(stats.winner, 0);
+    assert_eq!(stats.scores, [1000, 745]);
+    assert_eq!(stats.rolls, 993);
+    assert_eq!(stats.turns, 331);
+    assert_eq!(stats.puzzle_answer(), 739785);
+    assert_eq!(
+        stats.history[..2],
+        [
+            TurnRecord {
+                player: 0,
+                roll: 6,
+                position: 10,
+                score: 10
+            },
+            TurnRecord {
+                player: 1,
+                roll: 15,
+                position: 3,
+                score: 3
+            }
+        ]
+    );
+
+    // Seeded dice replay the same rolls.
+    let rolls = |seed| {
+        let mut die = RandomDie::new(6, seed);
+        (0..1000).map(|_| die.roll()).collect_vec()
+    };
+    assert_eq!(rolls(1), rolls(1));
+    assert_ne!(rolls(1), rolls(2));
+    let counts = rolls(1).into_iter().counts();
+    assert_eq!(
+        counts.keys().sorted().collect_vec(),
+        [&1, &2, &3, &4, &5, &6]
+    );
+
+    let mut die = LoadedDie::new(vec![(1, 1), (2, 0), (6, 3)], 7);
+    let counts = (0..4000).map(|_| die.roll()).counts();
+    assert_eq!(die.rolls(), 4000);
+    assert_eq!(counts.get(&2), None);
+    assert!((2800..3200).contains(&counts[&6]), "{:?}", counts);
+    let stats = play_game(
+        &[1, 1, 1],
+        &GameRules {
+            players: 3,
+            ..PRACTICE_RULES
         },
-        1,
+        &mut die,
     );
+    assert_eq!(stats.rolls, 3 * stats.turns);
+    assert!(stats.scores[stats.winner] >= 1000);
 
-    let mut totalp1_wins = 0;
-    let mut totalp2_wins = 0;
-    for _ in 0..21 {
-        let (new_worlds, p1wins, p2wins) = step_world(&worlds);
-        worlds = new_worlds;
-        totalp1_wins += p1wins;
-        totalp2_wins += p2wins;
+    // A die with negative faces moves players backwards round the board.
+    assert_eq!(PRACTICE_RULES.advance(2, -3), 9);
+    assert_eq!(PRACTICE_RULES.advance(1, -10), 1);
+    let mut die = LoadedDie::new(vec![(-1, 1)], 7);
+    let stats = play_game(
+        &[1, 1],
+        &GameRules {
+            target_score: 20,
+            ..PRACTICE_RULES
+        },
+        &mut die,
+    );
+    assert_eq!(stats.history[0].position, 8);
+    assert_eq!(stats.history[2].position, 5);
+    assert!(stats.history.iter().all(|t| (1..=10).contains(&t.position)));
+}
+
+#[test]
+fn test_strategy() {
+    // With no real choices, expectimax is just the exact solver.
+    let rules = GameRules {
+        target_score: 12,
+        ..DIRAC_RULES
+    };
+    let start = GameState::new(&[4, 8]);
+    let forward = vec![vec![Choice::Forward]; 2];
+    let value = StrategySolver::new(&rules, forward).value(&start, 0, false);
+    let endings = DiracSolver::new(&rules).endings(&start, 0);
+    for player in 0..2 {
+        let exact: f64 = endings.wins[player]
+            .iter()
+            .enumerate()
+            .map(|(turns, count)| *count as f64 / 27f64.powi(turns as i32))
+            .sum();
+        assert!((value[player] - exact).abs() < 1e-12);
+    }
+
+    // Extra options for the first player can only help them, and someone
+    // always wins.
+    let mut previous = 0.0;
+    for options in [
+        vec![Choice::Forward],
+        vec![Choice::Forward, Choice::Backward],
+        vec![Choice::Forward, Choice::Backward, Choice::Reroll],
+    ] {
+        let mut solver = StrategySolver::new(&rules, vec![options, vec![Choice::Forward]]);
+        let value = solver.value(&start, 0, false);
+        assert!(value[0] >= previous);
+        assert!((value[0] + value[1] - 1.0).abs() < 1e-12);
+        previous = value[0];
+    }
+    assert!(previous > 0.5);
+
+    // Ten points short with space 10 three behind, and the opponent sure to
+    // win on their next turn, a roll of 3 only wins by going backward.
+    let rules = GameRules {
+        die_faces: 3,
+        rolls_per_turn: 1,
+        target_score: 20,
+        ..DIRAC_RULES
+    };
+    let state = GameState {
+        positions: vec![3, 1],
+        scores: vec![10, 19],
+    };
+    let both = vec![Choice::Forward, Choice::Backward];
+    let mut solver = StrategySolver::new(&rules, vec![both.clone(), both]);
+    let (choice, outcome) = solver.decide(&state, 0, 3, false);
+    assert_eq!(choice, Choice::Backward);
+    assert_eq!(*outcome, [1.0, 0.0]);
+    let forward = vec![vec![Choice::Forward]; 2];
+    let forward_value = StrategySolver::new(&rules, forward).value(&state, 0, false);
+    assert!(solver.value(&state, 0, false)[0] > forward_value[0]);
+}
+
+#[test]
+fn test_parse_starts() {
+    let example = "Player 1 starting position: 4\nPlayer 2 starting position: 8\n";
+    let starts = parse_starts(example, 10).unwrap();
+    assert_eq!(starts, [4, 8]);
+    let stats = play_game(&starts, &PRACTICE_RULES, &mut PRACTICE_RULES.practice_die());
+    assert_eq!(stats.puzzle_answer(), 739785);
+    assert_eq!(
+        dirac_wins(&DIRAC_RULES, &starts),
+        [444356092776315, 341960390180808]
+    );
+
+    let three = "Player 1 starting position: 1\n\nPlayer 2 starting position: 5\n  Player 3 starting position: 10  \n";
+    let starts = parse_starts(three, 10).unwrap();
+    assert_eq!(starts, [1, 5, 10]);
+    let rules = GameRules {
+        players: 3,
+        target_score: 8,
+        ..DIRAC_RULES
+    };
+    assert_eq!(
+        solve(&rules, &starts).wins.iter().sum::<i128>(),
+        dirac_wins(&rules, &starts).iter().sum::<i128>()
+    );
+
+    assert_eq!(parse_starts("", 10), Err(StartError::NoPlayers));
+    assert_eq!(
+        parse_starts("Player 1 starting position: 4\nPlayer 2 starts at 8", 10),
+        Err(StartError::BadLine { line: 2 })
+    );
+    assert_eq!(
+        parse_starts("Player 2 starting position: 4", 10),
+        Err(StartError::WrongPlayer {
+            line: 1,
+            expected: 1,
+            found: 2
+        })
+    );
+    for position in [0, 11] {
+        assert_eq!(
+            parse_starts(&format!("Player 1 starting position: {}", position), 10),
+            Err(StartError::OutOfRange { line: 1, position })
+        );
     }
-    println!("totalwins {} {}", totalp1_wins, totalp2_wins);
+    assert_eq!(
+        parse_starts("Player 1 starting position: 11", 12),
+        Ok(vec![11])
+    );
 }
@@ -1,126 +1,627 @@
-use itertools::EitherOrBoth;
-use itertools::Itertools;
+use itertools::{iproduct, Itertools};
 use regex::Regex;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead};
 
+/// An axis-aligned box of cubes in `N` dimensions. Both bounds are inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Cuboid<const N: usize> {
+    lower: [i64; N],
+    upper: [i64; N],
+}
+
 #[derive(Debug)]
-struct Op {
+struct Op<const N: usize> {
     on: bool,
-    lower: [i64; 3],
-    upper: [i64; 3],
+    cuboid: Cuboid<N>,
 }
 
-fn load_ops() -> Vec<Op> {
-    let file = File::open("22.txt").unwrap();
-    let lines: io::Lines<io::BufReader<File>> = io::BufReader::new(file).lines();
+impl<const N: usize> Cuboid<N> {
+    fn new(lower: [i64; N], upper: [i64; N]) -> Cuboid<N> {
+        assert!((0..N).all(|i| lower[i] <= upper[i]), "empty cuboid");
+        Cuboid { lower, upper }
+    }
 
-    let re = Regex::new(r"(on|off) x=(.*)\.\.(.*),y=(.*)\.\.(.*),z=(.*)\.\.(.*)").unwrap();
-    return lines
-        .map(|l| {
-            let line = l.unwrap();
-            let captures = re.captures(line.as_str()).unwrap();
-            Op {
-                on: captures.get(1).unwrap().as_str() == "on",
-                lower: [
-                    captures.get(2).unwrap().as_str().parse().unwrap(),
-                    captures.get(4).unwrap().as_str().parse().unwrap(),
-                    captures.get(6).unwrap().as_str().parse().unwrap(),
-                ],
-                upper: [
-                    captures.get(3).unwrap().as_str().parse().unwrap(),
-                    captures.get(5).unwrap().as_str().parse().unwrap(),
-                    captures.get(7).unwrap().as_str().parse().unwrap(),
-                ],
+    fn intersection(&self, other: &Cuboid<N>) -> Option<Cuboid<N>> {
+        let mut result = *self;
+        for i in 0..N {
+            result.lower[i] = std::cmp::max(self.lower[i], other.lower[i]);
+            result.upper[i] = std::cmp::min(self.upper[i], other.upper[i]);
+            if result.lower[i] > result.upper[i] {
+                return None;
             }
-        })
-        .collect_vec();
+        }
+        Some(result)
+    }
+
+    /// The cubes of `self` outside `other`, as at most `2 * N` disjoint cuboids.
+    fn subtract(&self, other: &Cuboid<N>) -> Vec<Cuboid<N>> {
+        let overlap = match self.intersection(other) {
+            Some(overlap) => overlap,
+            None => return vec![*self],
+        };
+        let mut pieces = vec![];
+        let mut rest = *self;
+        for i in 0..N {
+            if rest.lower[i] < overlap.lower[i] {
+                let mut piece = rest;
+                piece.upper[i] = overlap.lower[i] - 1;
+                pieces.push(piece);
+                rest.lower[i] = overlap.lower[i];
+            }
+            if rest.upper[i] > overlap.upper[i] {
+                let mut piece = rest;
+                piece.lower[i] = overlap.upper[i] + 1;
+                pieces.push(piece);
+                rest.upper[i] = overlap.upper[i];
+            }
+        }
+        pieces
+    }
+
+    fn volume(&self) -> i64 {
+        (0..N).map(|i| self.upper[i] - self.lower[i] + 1).product()
+    }
+
+    fn contains(&self, point: &[i64; N]) -> bool {
+        (0..N).all(|i| self.lower[i] <= point[i] && point[i] <= self.upper[i])
+    }
 }
 
-#[test]
-fn part1() {
-    let mut grid = ndarray::Array::from_elem((101, 101, 101), false);
-    let ops = load_ops();
-    let bounded_ops = ops.iter().map(|op| Op {
-        on: op.on,
-        lower: op.lower.map(|l| std::cmp::max(l, -50)),
-        upper: op.upper.map(|u| std::cmp::min(u, 50)),
-    });
-    for op in bounded_ops {
-        for x in op.lower[0]..=op.upper[0] {
-            for y in op.lower[1]..=op.upper[1] {
-                for z in op.lower[2]..=op.upper[2] {
-                    grid[[(x + 50) as usize, (y + 50) as usize, (z + 50) as usize]] = op.on;
+/// A set of cubes, stored as disjoint cuboids.
+#[derive(Debug, Clone, Default)]
+struct CuboidSet<const N: usize> {
+    cuboids: Vec<Cuboid<N>>,
+}
+
+impl<const N: usize> CuboidSet<N> {
+    fn insert(&mut self, cuboid: Cuboid<N>) {
+        self.remove(&cuboid);
+        self.cuboids.push(cuboid);
+    }
+
+    fn remove(&mut self, cuboid: &Cuboid<N>) {
+        self.cuboids = self
+            .cuboids
+            .iter()
+            .flat_map(|c| c.subtract(cuboid))
+            .collect();
+    }
+
+    fn apply(&mut self, op: &Op<N>) {
+        if op.on {
+            self.insert(op.cuboid);
+        } else {
+            self.remove(&op.cuboid);
+        }
+    }
+
+    fn union(&self, other: &CuboidSet<N>) -> CuboidSet<N> {
+        let mut result = self.clone();
+        for cuboid in &other.cuboids {
+            result.insert(*cuboid);
+        }
+        result
+    }
+
+    fn intersection(&self, other: &CuboidSet<N>) -> CuboidSet<N> {
+        CuboidSet {
+            cuboids: iproduct!(&self.cuboids, &other.cuboids)
+                .filter_map(|(a, b)| a.intersection(b))
+                .collect(),
+        }
+    }
+
+    fn difference(&self, other: &CuboidSet<N>) -> CuboidSet<N> {
+        let mut result = self.clone();
+        for cuboid in &other.cuboids {
+            result.remove(cuboid);
+        }
+        result
+    }
+
+    /// The cubes of the set inside `bounds`.
+    fn clip(&self, bounds: &Cuboid<N>) -> CuboidSet<N> {
+        CuboidSet {
+            cuboids: self
+                .cuboids
+                .iter()
+                .filter_map(|c| c.intersection(bounds))
+                .collect(),
+        }
+    }
+
+    fn volume(&self) -> i64 {
+        self.cuboids.iter().map(Cuboid::volume).sum()
+    }
+
+    fn contains(&self, point: &[i64; N]) -> bool {
+        self.cuboids.iter().any(|c| c.contains(point))
+    }
+
+    /// The disjoint cuboids making up the set.
+    fn iter(&self) -> std::slice::Iter<'_, Cuboid<N>> {
+        self.cuboids.iter()
+    }
+}
+
+impl<const N: usize> From<Cuboid<N>> for CuboidSet<N> {
+    fn from(cuboid: Cuboid<N>) -> CuboidSet<N> {
+        CuboidSet {
+            cuboids: vec![cuboid],
+        }
+    }
+}
+
+/// The cubes that are on, as a sum of cuboids with signed multiplicities.
+/// Each op first cancels out everything already inside it by adding every
+/// overlap with the opposite sign, then adds itself if it turns cubes on.
+#[derive(Debug, Default)]
+struct Reactor<const N: usize> {
+    cuboids: HashMap<Cuboid<N>, i64>,
+}
+
+impl<const N: usize> Reactor<N> {
+    fn apply(&mut self, op: &Op<N>) {
+        let cuboid = op.cuboid;
+        let mut updates: HashMap<Cuboid<N>, i64> = HashMap::new();
+        for (existing, count) in &self.cuboids {
+            if let Some(overlap) = existing.intersection(&cuboid) {
+                *updates.entry(overlap).or_insert(0) -= count;
+            }
+        }
+        if op.on {
+            *updates.entry(cuboid).or_insert(0) += 1;
+        }
+        for (c, delta) in updates {
+            let count = self.cuboids.entry(c).or_insert(0);
+            *count += delta;
+            if *count == 0 {
+                self.cuboids.remove(&c);
+            }
+        }
+    }
+
+    fn volume(&self) -> i64 {
+        self.cuboids
+            .iter()
+            .map(|(cuboid, count)| cuboid.volume() * count)
+            .sum()
+    }
+
+    fn is_on(&self, point: &[i64; N]) -> bool {
+        self.cuboids
+            .iter()
+            .filter(|(cuboid, _)| cuboid.contains(point))
+            .map(|(_, count)| count)
+            .sum::<i64>()
+            > 0
+    }
+}
+
+/// How many cubes a reboot step changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StepReport {
+    turned_on: i64,
+    turned_off: i64,
+}
+
+/// The final state of some cubes, and the step that last covered them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CubeState<const N: usize> {
+    cuboid: Cuboid<N>,
+    on: bool,
+    /// `None` if no step covered these cubes, so they were never turned on.
+    last_step: Option<usize>,
+}
+
+/// Runs reboot steps while remembering which step last covered every cube.
+#[derive(Debug, Default)]
+struct RebootHistory<const N: usize> {
+    /// Disjoint cuboids covering every cube any step touched, each with the
+    /// last step covering it.
+    regions: Vec<(Cuboid<N>, usize)>,
+    /// Whether each step turned cubes on.
+    steps: Vec<bool>,
+    reports: Vec<StepReport>,
+}
+
+impl<const N: usize> RebootHistory<N> {
+    fn apply(&mut self, op: &Op<N>) -> StepReport {
+        let step = self.steps.len();
+        let mut previously_on = 0;
+        let mut regions = vec![];
+        for (cuboid, last_step) in &self.regions {
+            if let Some(overlap) = cuboid.intersection(&op.cuboid) {
+                if self.steps[*last_step] {
+                    previously_on += overlap.volume();
                 }
+                regions.extend(
+                    cuboid
+                        .subtract(&op.cuboid)
+                        .into_iter()
+                        .map(|c| (c, *last_step)),
+                );
+            } else {
+                regions.push((*cuboid, *last_step));
             }
         }
+        regions.push((op.cuboid, step));
+        self.regions = regions;
+        self.steps.push(op.on);
+        let report = if op.on {
+            StepReport {
+                turned_on: op.cuboid.volume() - previously_on,
+                turned_off: 0,
+            }
+        } else {
+            StepReport {
+                turned_on: 0,
+                turned_off: previously_on,
+            }
+        };
+        self.reports.push(report);
+        report
+    }
+
+    fn volume(&self) -> i64 {
+        self.regions
+            .iter()
+            .filter(|(_, step)| self.steps[*step])
+            .map(|(cuboid, _)| cuboid.volume())
+            .sum()
+    }
+
+    /// Whether the cube at `point` is on, and the step that last covered it.
+    fn query(&self, point: &[i64; N]) -> (bool, Option<usize>) {
+        match self.regions.iter().find(|(c, _)| c.contains(point)) {
+            Some((_, step)) => (self.steps[*step], Some(*step)),
+            None => (false, None),
+        }
+    }
+
+    /// Splits `region` into disjoint pieces, each with a single final state
+    /// and last step.
+    fn query_region(&self, region: &Cuboid<N>) -> Vec<CubeState<N>> {
+        let mut untouched = CuboidSet::from(*region);
+        let mut result = vec![];
+        for (cuboid, step) in &self.regions {
+            if let Some(overlap) = cuboid.intersection(region) {
+                untouched.remove(&overlap);
+                result.push(CubeState {
+                    cuboid: overlap,
+                    on: self.steps[*step],
+                    last_step: Some(*step),
+                });
+            }
+        }
+        result.extend(untouched.iter().map(|cuboid| CubeState {
+            cuboid: *cuboid,
+            on: false,
+            last_step: None,
+        }));
+        result
     }
-    println!("grid on {}", grid.iter().filter(|b| **b).count());
 }
 
-fn cell_width(coords: &EitherOrBoth<&i64, &i64>) -> i64 {
-    match coords {
-        EitherOrBoth::Both(x, next) => *next - *x,
-        EitherOrBoth::Left(_) => 1,
-        EitherOrBoth::Right(_) => {
-            panic!()
+fn load_ops() -> Vec<Op<3>> {
+    let file = File::open("22.txt").unwrap();
+    let lines: io::Lines<io::BufReader<File>> = io::BufReader::new(file).lines();
+    let (axes, ops) = parse_ops(lines.map(|l| l.unwrap())).unwrap();
+    assert_eq!(axes, ["x", "y", "z"]);
+    return ops;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpsError {
+    /// A line, counting from 1, not of the form `on x=10..12,y=10..12`, or
+    /// with a range whose lower bound is above its upper bound.
+    BadLine { line: usize },
+    /// A line with a different number of axes than the ops have.
+    WrongAxisCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A line naming different axes than the first line.
+    WrongAxes { line: usize },
+}
+
+/// Parses steps like `on x=10..12,y=10..12,z=10..12`, with `N` named axes.
+/// Returns the axis names, which must be the same on every line.
+fn parse_ops<const N: usize, I: Iterator<Item = String>>(
+    lines: I,
+) -> Result<(Vec<String>, Vec<Op<N>>), OpsError> {
+    let re = Regex::new(r"^(on|off) (.*)$").unwrap();
+    let axis_re = Regex::new(r"^(\w+)=(-?\d+)\.\.(-?\d+)$").unwrap();
+    let mut axes: Option<Vec<String>> = None;
+    let mut ops = vec![];
+    for (i, line) in lines.enumerate() {
+        let bad_line = OpsError::BadLine { line: i + 1 };
+        let captures = re.captures(line.as_str()).ok_or(bad_line)?;
+        let ranges = captures[2]
+            .split(',')
+            .map(|range| axis_re.captures(range).ok_or(bad_line))
+            .collect::<Result<Vec<_>, _>>()?;
+        if ranges.len() != N {
+            return Err(OpsError::WrongAxisCount {
+                line: i + 1,
+                expected: N,
+                found: ranges.len(),
+            });
+        }
+        let names = ranges.iter().map(|c| c[1].to_string()).collect_vec();
+        if axes.get_or_insert_with(|| names.clone()) != &names {
+            return Err(OpsError::WrongAxes { line: i + 1 });
         }
+        let bounds = |group: usize| -> Result<[i64; N], OpsError> {
+            let values = ranges
+                .iter()
+                .map(|c| c[group].parse().map_err(|_| bad_line))
+                .collect::<Result<Vec<i64>, _>>()?;
+            Ok(<[i64; N]>::try_from(values).unwrap())
+        };
+        let (lower, upper) = (bounds(2)?, bounds(3)?);
+        if (0..N).any(|k| lower[k] > upper[k]) {
+            return Err(bad_line);
+        }
+        ops.push(Op {
+            on: &captures[1] == "on",
+            cuboid: Cuboid::new(lower, upper),
+        });
     }
+    Ok((axes.unwrap_or_default(), ops))
 }
+
+#[test]
+fn part1() {
+    let ops = load_ops();
+    let region = Cuboid::new([-50; 3], [50; 3]);
+    let mut set = CuboidSet::default();
+    for op in &ops {
+        if let Some(cuboid) = op.cuboid.intersection(&region) {
+            set.apply(&Op { on: op.on, cuboid });
+        }
+    }
+    println!("disjoint cuboids {}", set.iter().len());
+    println!("grid on {}", set.volume());
+}
+
 #[test]
 fn part2() {
     let ops = load_ops();
-    let mut coords = [vec![], vec![], vec![]];
-    for i in 0..3 {
-        coords[i] = ops
-            .iter()
-            .flat_map(|op| [op.lower[i], op.upper[i] + 1].into_iter())
-            .collect();
-        coords[i].sort();
-        coords[i].dedup();
-        println!("coords size {}", coords[i].len());
+    let mut reactor = Reactor::default();
+    for (i, op) in ops.iter().enumerate() {
+        reactor.apply(op);
+        println!("op {} signed cuboids {}", i, reactor.cuboids.len());
     }
-    let mut grid = ndarray::Array::from_elem::<(usize, usize, usize)>(
-        coords.iter().map(|c| c.len()).collect_tuple().unwrap(),
-        false,
-    );
-    let range = |op: &Op, i: usize| {
-        coords[i].binary_search(&op.lower[i]).unwrap()
-            ..coords[i].binary_search(&(op.upper[i] + 1)).unwrap()
-    };
+    println!("total on {}", reactor.volume());
+}
+
+#[test]
+fn step_reports() {
+    let ops = load_ops();
+    let mut history = RebootHistory::default();
     for (i, op) in ops.iter().enumerate() {
-        println!("computing op {}", i);
-        for x in range(&op, 0) {
-            for y in range(&op, 1) {
-                for z in range(&op, 2) {
-                    grid[[x, y, z]] = op.on;
-                }
-            }
-        }
+        let report = history.apply(op);
+        println!(
+            "step {} turned on {} turned off {}",
+            i, report.turned_on, report.turned_off
+        );
     }
+    println!("total on {}", history.volume());
+    println!("regions {}", history.regions.len());
+    let origin = history.query(&[0, 0, 0]);
+    println!("origin on {} last step {:?}", origin.0, origin.1);
+}
 
-    let mut total_on = 0;
-    for (x, xsize) in coords[0]
+#[test]
+fn test_reactor_small_example() {
+    let (_, ops) = parse_ops::<3, _>(
+        [
+            "on x=10..12,y=10..12,z=10..12",
+            "on x=11..13,y=11..13,z=11..13",
+            "off x=9..11,y=9..11,z=9..11",
+            "on x=10..10,y=10..10,z=10..10",
+        ]
         .iter()
-        .zip_longest(coords[0].iter().skip(1))
-        .enumerate()
-    {
-        println!("computing x {} {:?}", x, xsize);
-        for (y, ysize) in coords[1]
+        .map(|l| l.to_string()),
+    )
+    .unwrap();
+    let mut reactor = Reactor::default();
+    let expected_volumes = [27, 27 + 19, 27 + 19 - 8, 39];
+    for (op, expected) in ops.iter().zip(expected_volumes) {
+        reactor.apply(op);
+        assert_eq!(reactor.volume(), expected);
+    }
+    assert!(reactor.is_on(&[10, 10, 10]));
+    assert!(!reactor.is_on(&[11, 11, 11]));
+    assert!(reactor.is_on(&[13, 13, 13]));
+    assert!(!reactor.is_on(&[14, 13, 13]));
+}
+
+#[test]
+fn test_cuboid_set_algebra() {
+    let a = CuboidSet::from(Cuboid::new([0, 0, 0], [9, 9, 9]))
+        .difference(&CuboidSet::from(Cuboid::new([3, 3, 3], [5, 5, 5])));
+    let b = CuboidSet::from(Cuboid::new([5, 5, 5], [14, 6, 7]))
+        .union(&CuboidSet::from(Cuboid::new([-4, 0, 0], [0, 0, 0])));
+    assert_eq!(a.volume(), 1000 - 27);
+    assert_eq!(b.volume(), 10 * 2 * 3 + 5);
+
+    let union = a.union(&b);
+    let intersection = a.intersection(&b);
+    let difference = a.difference(&b);
+    assert_eq!(
+        union.volume(),
+        a.volume() + b.volume() - intersection.volume()
+    );
+    assert_eq!(difference.volume(), a.volume() - intersection.volume());
+    for set in [&a, &b, &union, &intersection, &difference] {
+        assert!(set
             .iter()
-            .zip_longest(coords[1].iter().skip(1))
-            .enumerate()
-        {
-            for (z, zsize) in coords[2]
-                .iter()
-                .zip_longest(coords[2].iter().skip(1))
-                .enumerate()
-            {
-                if grid[[x, y, z]] {
-                    total_on += cell_width(&xsize) * cell_width(&ysize) * cell_width(&zsize);
-                }
+            .tuple_combinations()
+            .all(|(x, y)| x.intersection(y).is_none()));
+    }
+    for point in iproduct!(-5..16, -1..11, -1..11).map(|(x, y, z)| [x, y, z]) {
+        let (in_a, in_b) = (a.contains(&point), b.contains(&point));
+        assert_eq!(union.contains(&point), in_a || in_b);
+        assert_eq!(intersection.contains(&point), in_a && in_b);
+        assert_eq!(difference.contains(&point), in_a && !in_b);
+    }
+
+    let clipped = union.clip(&Cuboid::new([-2, 0, 0], [7, 0, 0]));
+    assert_eq!(clipped.volume(), 10);
+}
+
+/// Applies random ops inside `[0, size)` on every axis, checking the reactor
+/// and the cuboid set against every point after each one.
+fn check_against_points<const N: usize>(seed: u64, size: i64, num_ops: usize) {
+    let mut rng = crate::Lcg::new(seed);
+    let points = (0..N)
+        .map(|_| 0..size)
+        .multi_cartesian_product()
+        .map(|p| <[i64; N]>::try_from(p).unwrap())
+        .collect_vec();
+    let mut on = vec![false; points.len()];
+    let mut reactor = Reactor::default();
+    let mut set = CuboidSet::default();
+    for _ in 0..num_ops {
+        let a: [i64; N] = std::array::from_fn(|_| rng.range(0, size - 1));
+        let b: [i64; N] = std::array::from_fn(|_| rng.range(0, size - 1));
+        let op = Op {
+            on: rng.range(0, 1) == 1,
+            cuboid: Cuboid::new(
+                std::array::from_fn(|i| std::cmp::min(a[i], b[i])),
+                std::array::from_fn(|i| std::cmp::max(a[i], b[i])),
+            ),
+        };
+        for (point, state) in points.iter().zip(on.iter_mut()) {
+            if op.cuboid.contains(point) {
+                *state = op.on;
             }
         }
+        reactor.apply(&op);
+        set.apply(&op);
+        let expected = on.iter().filter(|b| **b).count() as i64;
+        assert_eq!(reactor.volume(), expected);
+        assert_eq!(set.volume(), expected);
+    }
+    for (point, state) in points.iter().zip(&on) {
+        assert_eq!(reactor.is_on(point), *state);
+        assert_eq!(set.contains(point), *state);
+    }
+    assert!(set
+        .iter()
+        .tuple_combinations()
+        .all(|(a, b)| a.intersection(b).is_none()));
+}
+
+#[test]
+fn test_reactor_matches_points() {
+    check_against_points::<1>(1, 30, 20);
+    check_against_points::<2>(2, 20, 40);
+    check_against_points::<3>(22, 20, 60);
+    check_against_points::<4>(4, 6, 40);
+}
+
+#[test]
+fn test_parse_named_axes() {
+    let (axes, ops) = parse_ops::<2, _>(
+        ["on a=-3..4,b=0..0", "off a=1..1,b=-2..2"]
+            .iter()
+            .map(|l| l.to_string()),
+    )
+    .unwrap();
+    assert_eq!(axes, ["a", "b"]);
+    let mut reactor = Reactor::default();
+    for op in &ops {
+        reactor.apply(op);
+    }
+    assert_eq!(reactor.volume(), 7);
+
+    let (axes, ops) = parse_ops::<4, _>(
+        [
+            "on w=0..1,x=0..1,y=0..1,z=0..1",
+            "off w=1..1,x=1..1,y=1..1,z=1..1",
+        ]
+        .iter()
+        .map(|l| l.to_string()),
+    )
+    .unwrap();
+    assert_eq!(axes, ["w", "x", "y", "z"]);
+    let mut set = CuboidSet::default();
+    for op in &ops {
+        set.apply(op);
+    }
+    assert_eq!(set.volume(), 15);
+
+    let parse = |lines: &[&str]| parse_ops::<2, _>(lines.iter().map(|l| l.to_string())).err();
+    assert_eq!(
+        parse(&["on a=0..1,b=0..1", "on a=0..1"]),
+        Some(OpsError::WrongAxisCount {
+            line: 2,
+            expected: 2,
+            found: 1
+        })
+    );
+    assert_eq!(
+        parse(&["on a=0..1,b=0..1", "off a=0..1,c=0..1"]),
+        Some(OpsError::WrongAxes { line: 2 })
+    );
+    assert_eq!(
+        parse(&["on a=0..1,b=2..1"]),
+        Some(OpsError::BadLine { line: 1 })
+    );
+    assert_eq!(
+        parse(&["toggle a=0..1,b=0..1"]),
+        Some(OpsError::BadLine { line: 1 })
+    );
+}
+
+#[test]
+fn test_reboot_history() {
+    let (_, ops) = parse_ops::<3, _>(
+        [
+            "on x=10..12,y=10..12,z=10..12",
+            "on x=11..13,y=11..13,z=11..13",
+            "off x=9..11,y=9..11,z=9..11",
+            "on x=10..10,y=10..10,z=10..10",
+        ]
+        .iter()
+        .map(|l| l.to_string()),
+    )
+    .unwrap();
+    let mut history = RebootHistory::default();
+    let reports = ops.iter().map(|op| history.apply(op)).collect_vec();
+    assert_eq!(
+        reports
+            .iter()
+            .map(|r| (r.turned_on, r.turned_off))
+            .collect_vec(),
+        [(27, 0), (19, 0), (0, 8), (1, 0)]
+    );
+    assert_eq!(history.volume(), 39);
+    assert_eq!(history.query(&[10, 10, 10]), (true, Some(3)));
+    assert_eq!(history.query(&[11, 11, 11]), (false, Some(2)));
+    assert_eq!(history.query(&[9, 9, 9]), (false, Some(2)));
+    assert_eq!(history.query(&[12, 12, 12]), (true, Some(1)));
+    assert_eq!(history.query(&[12, 10, 10]), (true, Some(0)));
+    assert_eq!(history.query(&[0, 0, 0]), (false, None));
+
+    let region = Cuboid::new([8, 8, 8], [12, 12, 12]);
+    let pieces = history.query_region(&region);
+    assert_eq!(pieces.iter().map(|p| p.cuboid.volume()).sum::<i64>(), 125);
+    for point in iproduct!(8..=12, 8..=12, 8..=12).map(|(x, y, z)| [x, y, z]) {
+        let containing = pieces
+            .iter()
+            .filter(|p| p.cuboid.contains(&point))
+            .collect_vec();
+        assert_eq!(containing.len(), 1);
+        assert_eq!(
+            (containing[0].on, containing[0].last_step),
+            history.query(&point)
+        );
     }
-    println!("total on {}", total_on);
 }